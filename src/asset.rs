@@ -0,0 +1,331 @@
+use crate::prelude::*;
+use async_std::io::{prelude::SeekExt, BufReader, ReadExt};
+use flate2::write::GzEncoder;
+use once_cell::sync::Lazy;
+use std::io::{SeekFrom, Write};
+use std::ops::Range;
+use std::time::UNIX_EPOCH;
+use tide::http::conditional::{ETag, IfModifiedSince, IfNoneMatch, LastModified};
+use tide::http::content::{AcceptEncoding, ContentEncoding, Encoding};
+use tide::http::{headers, mime, Mime};
+use tide::{Body, Request, Response, StatusCode};
+
+/// Content encodings negotiated by the dev server, in order of preference
+const ENCODINGS: &[Encoding] = &[Encoding::Brotli, Encoding::Gzip];
+
+/// Content smaller than this is not worth compressing
+const COMPRESS_THRESHOLD: u64 = 1024;
+
+/// Compressed content cached by encoded ETag (`<etag>-<encoding>`)
+static COMPRESSED: Lazy<Mutex<HashMap<String, Arc<Vec<u8>>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Number of compressed assets kept in the cache
+const COMPRESSED_CACHE_SIZE: usize = 256;
+
+/// Non-text mime types that benefit from compression
+const COMPRESSIBLE: &[&str] = &[
+    "application/javascript",
+    "application/json",
+    "application/wasm",
+    "application/xml",
+    "image/svg+xml",
+];
+
+enum Content {
+    Memory(Arc<Vec<u8>>),
+    File(PathBuf),
+}
+
+/// A file served by the dev server along with the metadata used
+/// for content negotiation, conditional and range requests
+pub struct Asset {
+    content: Content,
+    len: u64,
+    modified: SystemTime,
+    mime: Option<Mime>,
+    etag: String,
+}
+
+impl Asset {
+    /// Create an asset for the output store `entry` of `file`
+    /// (relative to the site folder)
+    pub async fn try_new(file: &Path, entry: Entry) -> Result<Asset> {
+        let mime = file
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(Mime::from_extension);
+
+        let asset = match entry {
+            Entry::Content(content, modified) => Asset {
+                len: content.len() as u64,
+                etag: format!("{:x}", make_hash(&content)),
+                content: Content::Memory(content),
+                modified,
+                mime,
+            },
+            Entry::File(path) => {
+                let metadata = fs::metadata(&path).await?;
+                if !metadata.is_file() {
+                    return Err(format!("`{}` is not a file", path.display()).into());
+                }
+                let modified = metadata.modified()?;
+                let stamp = modified
+                    .duration_since(UNIX_EPOCH)
+                    .map(|duration| duration.as_nanos())
+                    .unwrap_or_default();
+                let etag = format!(
+                    "{:x}",
+                    make_hash(format!("{}:{}:{stamp}", path.display(), metadata.len()).as_bytes())
+                );
+                Asset {
+                    content: Content::File(path),
+                    len: metadata.len(),
+                    modified,
+                    mime,
+                    etag,
+                }
+            }
+        };
+
+        Ok(asset)
+    }
+
+    /// Serve the asset as HTML regardless of the file extension
+    pub fn html(mut self) -> Self {
+        self.mime = Some(mime::HTML);
+        self
+    }
+
+    /// Respond to `request`, honoring `If-None-Match`/`If-Modified-Since`,
+    /// `Range` and `Accept-Encoding` request headers
+    pub async fn respond<State>(&self, request: &Request<State>) -> tide::Result {
+        let mut response = Response::new(StatusCode::Ok);
+        response.insert_header(headers::ACCEPT_RANGES, "bytes");
+        // browsers revalidate on each request, receiving 304 for unchanged content
+        response.insert_header(headers::CACHE_CONTROL, "no-cache");
+        LastModified::new(self.modified).apply(&mut response);
+
+        // range requests are served from the identity content
+        let encoding = if self.is_compressible() {
+            response.insert_header(headers::VARY, "accept-encoding");
+            if request.header("range").is_none() {
+                self.encoding(request)
+            } else {
+                None
+            }
+        } else {
+            None
+        };
+        let etag = match encoding {
+            Some(encoding) => format!("{}-{encoding}", self.etag),
+            None => self.etag.clone(),
+        };
+        ETag::new(etag.clone()).apply(&mut response);
+
+        if self.is_fresh(request) {
+            response.set_status(StatusCode::NotModified);
+            return Ok(response);
+        }
+
+        if let Some(range) = self.requested_range(request) {
+            match range {
+                Some(range) => {
+                    response.set_status(StatusCode::PartialContent);
+                    response.insert_header(
+                        headers::CONTENT_RANGE,
+                        format!("bytes {}-{}/{}", range.start, range.end - 1, self.len),
+                    );
+                    response.set_body(self.body(range).await?);
+                }
+                None => {
+                    response.set_status(StatusCode::RequestedRangeNotSatisfiable);
+                    response.insert_header(headers::CONTENT_RANGE, format!("bytes */{}", self.len));
+                }
+            }
+            return Ok(response);
+        }
+
+        match encoding {
+            Some(encoding) => {
+                let content = self.compressed(&etag, encoding).await?;
+                let mut body = Body::from_bytes(content.to_vec());
+                if let Some(mime) = &self.mime {
+                    body.set_mime(mime.clone());
+                }
+                response.set_body(body);
+                ContentEncoding::new(encoding).apply(&mut response);
+            }
+            None => {
+                response.set_body(self.body(0..self.len).await?);
+            }
+        }
+
+        Ok(response)
+    }
+
+    fn is_compressible(&self) -> bool {
+        self.len >= COMPRESS_THRESHOLD
+            && self
+                .mime
+                .as_ref()
+                .map(|mime| mime.basetype() == "text" || COMPRESSIBLE.contains(&mime.essence()))
+                .unwrap_or(false)
+    }
+
+    /// Negotiate the content encoding from the `Accept-Encoding` header
+    fn encoding<State>(&self, request: &Request<State>) -> Option<Encoding> {
+        let mut accept = AcceptEncoding::from_headers(request).ok().flatten()?;
+        let encoding = accept.negotiate(ENCODINGS).ok()?;
+        Some(*encoding)
+    }
+
+    /// Check if the client already holds the current content
+    fn is_fresh<State>(&self, request: &Request<State>) -> bool {
+        if let Ok(Some(matches)) = IfNoneMatch::from_headers(request) {
+            return matches.wildcard() || matches.iter().any(|etag| self.is_match(etag));
+        }
+
+        if let Ok(Some(since)) = IfModifiedSince::from_headers(request) {
+            // http dates have a resolution of one second
+            return seconds(self.modified) <= seconds(since.modified());
+        }
+
+        false
+    }
+
+    /// ETags of all encodings of the content match (weak comparison)
+    fn is_match(&self, etag: &ETag) -> bool {
+        let tag = match etag {
+            ETag::Strong(tag) | ETag::Weak(tag) => tag,
+        };
+        tag == &self.etag
+            || ENCODINGS
+                .iter()
+                .any(|encoding| *tag == format!("{}-{encoding}", self.etag))
+    }
+
+    /// Byte range requested with the `Range` header; `None` if the
+    /// whole content should be served and `Some(None)` if the range
+    /// can not be satisfied
+    fn requested_range<State>(&self, request: &Request<State>) -> Option<Option<Range<u64>>> {
+        let header = request.header("range")?.as_str();
+
+        // `If-Range` must match the current content exactly
+        if let Some(validator) = request.header(headers::IF_RANGE) {
+            let validator = validator.as_str().trim();
+            let matches = if validator.starts_with('"') {
+                validator.trim_matches('"') == self.etag
+            } else {
+                validator == LastModified::new(self.modified).value().as_str()
+            };
+            if !matches {
+                return None;
+            }
+        }
+
+        self.parse_range(header)
+    }
+
+    /// Parse a single `bytes=` range; multiple ranges and
+    /// malformed values result in the whole content being served
+    fn parse_range(&self, header: &str) -> Option<Option<Range<u64>>> {
+        let spec = header.trim().strip_prefix("bytes=")?;
+        if spec.contains(',') {
+            return None;
+        }
+        let (start, end) = spec.split_once('-')?;
+        let (start, end) = (start.trim(), end.trim());
+
+        if start.is_empty() {
+            // suffix range: the last `end` bytes
+            let suffix = end.parse::<u64>().ok()?;
+            if suffix == 0 || self.len == 0 {
+                return Some(None);
+            }
+            return Some(Some(self.len.saturating_sub(suffix)..self.len));
+        }
+
+        let start = start.parse::<u64>().ok()?;
+        let end = if end.is_empty() {
+            self.len
+        } else {
+            let end = end.parse::<u64>().ok()?;
+            if end < start {
+                return None;
+            }
+            (end + 1).min(self.len)
+        };
+
+        if start >= self.len {
+            Some(None)
+        } else {
+            Some(Some(start..end))
+        }
+    }
+
+    /// Content compressed with `encoding`, cached by its encoded `etag`
+    async fn compressed(&self, etag: &str, encoding: Encoding) -> Result<Arc<Vec<u8>>> {
+        if let Some(content) = COMPRESSED.lock().unwrap().get(etag) {
+            return Ok(content.clone());
+        }
+        let content = Arc::new(compress(&self.read().await?, encoding)?);
+        let mut cache = COMPRESSED.lock().unwrap();
+        if cache.len() >= COMPRESSED_CACHE_SIZE {
+            cache.clear();
+        }
+        cache.insert(etag.to_string(), content.clone());
+        Ok(content)
+    }
+
+    async fn read(&self) -> Result<Vec<u8>> {
+        match &self.content {
+            Content::Memory(content) => Ok(content.to_vec()),
+            Content::File(path) => Ok(fs::read(path).await?),
+        }
+    }
+
+    async fn body(&self, range: Range<u64>) -> Result<Body> {
+        let mut body = match &self.content {
+            Content::Memory(content) => {
+                Body::from_bytes(content[range.start as usize..range.end as usize].to_vec())
+            }
+            Content::File(path) => {
+                let len = range.end - range.start;
+                let mut file = fs::File::open(path).await?;
+                file.seek(SeekFrom::Start(range.start)).await?;
+                Body::from_reader(BufReader::new(file.take(len)), Some(len as usize))
+            }
+        };
+        if let Some(mime) = &self.mime {
+            body.set_mime(mime.clone());
+        }
+        Ok(body)
+    }
+}
+
+fn seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+fn compress(content: &[u8], encoding: Encoding) -> Result<Vec<u8>> {
+    match encoding {
+        Encoding::Brotli => {
+            let mut compressed = Vec::new();
+            {
+                // moderate quality keeps the dev server responsive
+                let mut writer = brotli::CompressorWriter::new(&mut compressed, 4096, 5, 22);
+                writer.write_all(content)?;
+            }
+            Ok(compressed)
+        }
+        Encoding::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(content)?;
+            Ok(encoder.finish()?)
+        }
+        _ => Ok(content.to_vec()),
+    }
+}
@@ -1,4 +1,7 @@
+use crate::data::{load_data, load_data_files, merge_data, DataFormat};
 use crate::prelude::*;
+use crate::rules::Rules;
+use crate::schema::FileKind;
 use std::sync::Mutex;
 use std::time::Instant;
 use tera::Filter as TeraFilter;
@@ -7,12 +10,16 @@ use workflow_i18n::Dict;
 
 const SERVER_STUBS_TPL: &str = include_str!("./server-stubs.html");
 
-static mut SERVER_STUBS: Option<Mutex<String>> = None;
+/// Templates rendered from the templates folder
+const TEMPLATES_GLOB: &str = "**/*{.html,.md,.js,.raw}";
+
+static SERVER_STUBS: Mutex<Option<String>> = Mutex::new(None);
 
 // fn server_stubs(tpl : &Option<HashMap<String,String>>) -> String {
 fn server_stubs(settings: &Option<Settings>) -> String {
-    let text = if let Some(text) = unsafe { &SERVER_STUBS } {
-        text.lock().unwrap().clone()
+    let mut server_stubs = SERVER_STUBS.lock().unwrap();
+    let text = if let Some(text) = server_stubs.as_ref() {
+        text.clone()
     } else {
         let mut text = SERVER_STUBS_TPL.to_string();
 
@@ -36,7 +43,7 @@ fn server_stubs(settings: &Option<Settings>) -> String {
             text
         };
 
-        unsafe { SERVER_STUBS = Some(Mutex::new(text.clone())) };
+        server_stubs.replace(text.clone());
         text
     };
 
@@ -54,12 +61,99 @@ pub struct Builder {
     ctx: Arc<Context>,
     sink: Sink,
     i18n_dict: Arc<Dict>,
+    /// page index of the current build
+    collection: Arc<Mutex<Collection>>,
 }
 
+/// Subsection settings file, located in a subfolder of a section folder
+pub const SUBSECTION_FILE: &str = "_section.toml";
+/// Template variables set for each page, shadowing manifest keys of the same name
+pub const RESERVED_KEYS: &[&str] = &["pages", "page", "section", "breadcrumbs"];
+/// Default output path of section files (see `SectionSettings::permalink`)
+pub const DEFAULT_PERMALINK: &str = "{section}/{path}{slug}.html";
+
 pub struct SectionInfo {
+    /// section name, followed by the subsection folders (i.e. `blog/2024`)
     name: String,
-    template_file: String,
-    files: Vec<String>,
+    title: Option<String>,
+    /// template rendering each file of the section (`enumerate = true`)
+    template_file: Option<String>,
+    /// template rendering the section index page
+    index_file: Option<String>,
+    /// output path pattern of the section files
+    permalink: String,
+    slugify: bool,
+    /// files of the section, excluding the files of its subsections
+    files: Vec<SectionFile>,
+    /// subsections at any depth (always empty for subsections)
+    subsections: Vec<SectionInfo>,
+}
+
+impl SectionInfo {
+    /// The section followed by its subsections
+    fn walk(&self) -> impl Iterator<Item = &SectionInfo> {
+        std::iter::once(self).chain(self.subsections.iter())
+    }
+
+    /// Output path of the section index page, relative to the language folder
+    fn index_destination(&self) -> Option<String> {
+        self.index_file
+            .as_ref()
+            .map(|_| format!("{}/index.html", self.name))
+    }
+}
+
+/// A file of a section folder, rendered via the section template
+#[derive(Debug, Clone)]
+pub struct SectionFile {
+    /// path relative to the project folder
+    path: String,
+    file: PathBuf,
+    slug: String,
+    /// output path relative to the language folder
+    destination: String,
+    /// path relative to the templates folder, exposed as the deprecated
+    /// `section_file` template variable
+    include: String,
+}
+
+impl SectionFile {
+    /// Hidden template name of an html section file
+    fn template(&self) -> String {
+        format!(".sections/{}", self.path)
+    }
+}
+
+/// Returns the output path of a template rendered as a page, or `None` if the
+/// template is a section template or is hidden or excluded
+fn page_destination(
+    template: &str,
+    sections: &HashMap<String, SectionInfo>,
+    exclude: &Filter,
+) -> Option<String> {
+    let root_folder = root_folder(template)?;
+    let mut destination = template.to_string();
+    if let Some(section) = sections.get(&root_folder) {
+        let section_template = section.walk().any(|section| {
+            [&section.template_file, &section.index_file]
+                .into_iter()
+                .flatten()
+                .any(|file| file.ends_with(template))
+        });
+        if section_template || is_file_hidden(template) {
+            return None;
+        }
+        destination = template.replace(&format!("{root_folder}/"), &format!("{}/", section.name));
+    } else if is_hidden(template) {
+        return None;
+    }
+
+    if exclude.is_match(template) {
+        log_trace!("Render", "{} `{}`", style("ignore:").yellow(), template);
+        return None;
+    }
+
+    Some(destination)
 }
 
 impl Builder {
@@ -68,6 +162,7 @@ impl Builder {
             ctx,
             sink: Sink::default(),
             i18n_dict: Arc::new(Dict::default()),
+            collection: Arc::new(Mutex::new(Collection::default())),
         }
     }
 
@@ -76,39 +171,25 @@ impl Builder {
             ctx,
             sink,
             i18n_dict: Arc::new(Dict::default()),
+            collection: Arc::new(Mutex::new(Collection::default())),
         }
     }
 
-    /// Migrate non-template files into the target directory
+    /// Migrate non-template files into the target directory; theme
+    /// assets are migrated unless overridden by a project file
     pub async fn migrate(&self, include: &Filter, exclude: &Filter) -> Result<()> {
-        let list = WalkDir::new(&self.ctx.src_folder)
+        let mut list: Vec<(PathBuf, PathBuf)> = vec![];
+        let mut files = AHashSet::new();
+        for folder in [&self.ctx.src_folder]
             .into_iter()
-            .flatten()
-            .filter_map(|entry| {
-                let path = entry.path();
-                let relative = path.strip_prefix(&self.ctx.src_folder).unwrap();
-
-                let relative_str = relative.to_str().unwrap();
-                if relative_str.is_empty() || is_hidden(relative) {
-                    return None;
-                }
-
-                if include.is_match(relative_str) || !path.is_file() {
-                    None
-                } else if exclude.is_match(relative_str) {
-                    log_trace!(
-                        "Migrate",
-                        "{} `{}`",
-                        style("ignore:").yellow(),
-                        path.display()
-                    );
-                    None
-                } else {
-                    Some(Path::new(relative).to_path_buf())
+            .chain(self.ctx.static_folders.iter())
+        {
+            for file in self.migrate_list(folder, include, exclude) {
+                if files.insert(file.clone()) {
+                    list.push((file.clone(), folder.join(file)));
                 }
-            });
-
-        let list: Vec<_> = list.collect();
+            }
+        }
 
         self.sink.migrate(&self.ctx, &list)?;
 
@@ -142,21 +223,74 @@ impl Builder {
         Ok(())
     }
 
-    fn save_file(&self, content: &str, template: &str, language: Option<&String>) -> Result<()> {
-        let target_file = if let Some(language) = language {
-            self.ctx.site_folder.join(language).join(template)
+    /// Returns the files of `folder` to migrate, relative to `folder`
+    fn migrate_list(&self, folder: &Path, include: &Filter, exclude: &Filter) -> Vec<PathBuf> {
+        WalkDir::new(folder)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| {
+                let path = entry.path();
+                let relative = path.strip_prefix(folder).unwrap();
+
+                let relative_str = relative.to_str().unwrap();
+                if relative_str.is_empty() || is_hidden(relative) {
+                    return None;
+                }
+
+                if include.is_match(relative_str) || !path.is_file() {
+                    None
+                } else if exclude.is_match(relative_str) {
+                    log_trace!(
+                        "Migrate",
+                        "{} `{}`",
+                        style("ignore:").yellow(),
+                        path.display()
+                    );
+                    None
+                } else {
+                    Some(Path::new(relative).to_path_buf())
+                }
+            })
+            .collect()
+    }
+
+    /// Save rendered content; a failed render (`None`) keeps the last
+    /// good page in place, so that the error overlay is shown on top of it
+    fn save_file(
+        &self,
+        content: Option<&str>,
+        template: &str,
+        language: Option<&String>,
+        sources: &[PathBuf],
+    ) -> Result<()> {
+        let (target_file, url) = if let Some(language) = language {
+            (
+                Path::new(language).join(template),
+                format!("/{language}/{template}"),
+            )
         } else {
-            self.ctx.site_folder.join(template)
+            (PathBuf::from(template), format!("/{template}"))
+        };
+        self.sink.page(&url, sources);
+        let stubs;
+        let content = match content {
+            Some(content) => content,
+            None if self.sink.exists(&self.ctx, &target_file) => {
+                self.sink.keep(&target_file);
+                return Ok(());
+            }
+            None => {
+                stubs = server_stubs(&self.ctx.manifest.settings);
+                &stubs
+            }
         };
-        let folder = target_file.parent().unwrap();
-        if !std::path::Path::new(folder).exists() {
-            std::fs::create_dir_all(folder)?;
-        }
         log_trace!("Render", "{} `{}`", style("render:").cyan(), template);
-        std::fs::write(target_file, content)?;
+        self.sink
+            .write(&self.ctx, &target_file, content.as_bytes())?;
         Ok(())
     }
 
+    /// Render a `template` into the page at `destination`, relative to `url_prefix`
     fn render_template(
         &self,
         tera: &tera::Tera,
@@ -164,25 +298,70 @@ impl Builder {
         context: &mut tera::Context,
         language: &Language,
         url_prefix: &str,
-    ) -> Result<String> {
+        destination: &str,
+    ) -> Result<Option<String>> {
         use std::error::Error;
 
+        let collection = self.collection.lock().unwrap();
+        // the page list is only replaced when the rendered language changes
+        let locale = tera::Value::from(language.locale.as_str());
+        if context.get("locale") != Some(&locale) || !context.contains_key("pages") {
+            context.insert("pages", &collection.pages(&language.locale));
+        }
+
         context.insert("url_prefix", url_prefix);
         context.insert("locale", &language.locale);
         context.insert("selected_language", &language);
 
+        let url = format!("{url_prefix}{destination}");
+        let page = collection.page_at(&url).cloned();
+        match &page {
+            Some(page) => context.insert("page", page),
+            None => {
+                context.remove("page");
+            }
+        }
+        match collection
+            .section_at(&url)
+            .and_then(|name| collection.section(&name, Some(&language.locale)))
+        {
+            Some(section) => context.insert("section", &section),
+            None => {
+                context.remove("section");
+            }
+        }
+        context.insert(
+            "breadcrumbs",
+            &collection.breadcrumbs(&url, &language.locale),
+        );
+        drop(collection);
+
         log_trace!(
             "Rendering",
             "{}",
             style(format!("{url_prefix}{template}")).blue()
         );
 
-        match tera.render(template, context) {
+        let content = match page.as_ref().and_then(|page| page.template.as_ref()) {
+            Some(content_template) => tera.render(content_template, context).map(Some),
+            None => Ok(None),
+        };
+        let result = content.and_then(|content| {
+            if let (Some(content), Some(mut page)) = (content, page) {
+                let (content, toc) = html_content(&content);
+                page.content = Some(content);
+                page.toc = toc;
+                context.insert("page", &page);
+            }
+            tera.render(template, context)
+        });
+
+        match result {
             Ok(mut s) => {
                 if self.ctx.options.server {
                     s += &server_stubs(&self.ctx.manifest.settings);
                 }
-                Ok(s)
+                Ok(Some(s))
             }
             Err(err) => {
                 let mut error_string = String::new();
@@ -195,10 +374,11 @@ impl Builder {
 
                 // Err(e.into())
                 if self.ctx.options.server {
-                    error_string += &server_stubs(&self.ctx.manifest.settings);
-                    Ok(error_string)
+                    self.sink
+                        .error(BuildError::from_tera(&err, &self.ctx.templates_folder));
+                    Ok(None)
                 } else {
-                    Ok(error_string)
+                    Ok(Some(error_string))
                 }
             }
         }
@@ -207,34 +387,103 @@ impl Builder {
     /// Render templates into the target directory
     pub async fn render(
         &self,
-        glob: &str,
         exclude: &Filter,
         settings: &Settings,
         sections: HashMap<String, SectionInfo>,
     ) -> Result<()> {
-        let project_folder = self.ctx.src_folder.clone();
-        let dir = self.ctx.src_folder.join(glob);
+        let templates_folder = self.ctx.templates_folder.clone();
+        let dir = templates_folder.join(TEMPLATES_GLOB);
         let dir = dir.to_str().unwrap();
         let mut tera = match tera::Tera::new(dir) {
             Ok(t) => t,
             Err(err) => {
-                log_error!("Parsing error(s): {err}, glob:{glob}");
+                log_error!("Parsing error(s): {err}, glob:{dir}");
+                self.sink
+                    .error(BuildError::from_tera(&err, &templates_folder));
+                self.sink.keep_all();
                 // return Err(err.into());
                 return Ok(());
             }
         };
 
+        // workspace templates only provide templates missing from the project
+        for folder in self.ctx.shared_templates.iter() {
+            let glob = folder.join(TEMPLATES_GLOB);
+            let shared = tera::Tera::new(glob.to_str().unwrap()).and_then(|shared| {
+                tera.extend(&shared)?;
+                Ok(())
+            });
+            if let Err(err) = shared {
+                log_error!("Parsing error(s): {err}, glob:{}", glob.display());
+                self.sink.error(BuildError::from_tera(&err, folder));
+                self.sink.keep_all();
+                return Ok(());
+            }
+        }
+
+        // html section files render the `page.content` of their page
+        let section_templates = sections
+            .values()
+            .flat_map(|section| section.walk())
+            .flat_map(|section| section.files.iter())
+            .filter(|file| file.path.ends_with(".html"))
+            .map(|file| (file.file.clone(), Some(file.template())))
+            .collect::<Vec<_>>();
+        if let Err(err) = tera.add_template_files(section_templates) {
+            log_error!("Parsing error(s): {err}");
+            self.sink
+                .error(BuildError::from_tera(&err, &self.ctx.project_folder));
+            self.sink.keep_all();
+            return Ok(());
+        }
+
         let mut context = tera::Context::from_serialize(&self.ctx.manifest.toml)?;
+        // `pages` is set per language by `render_template`
+        context.remove("pages");
         if let Some(sections) = &self.ctx.sections() {
             context.insert("sections", sections);
         }
+        // `build.env` is merged into a manifest `[build]` table
+        let mut build = match context.get("build") {
+            Some(tera::Value::Object(build)) => build.clone(),
+            _ => Default::default(),
+        };
+        build.insert("env".to_string(), self.ctx.manifest.env.clone().into());
+        context.insert("build", &build);
+        context.insert("base_path", &self.ctx.base_path);
+        match load_data_files(&self.ctx) {
+            Ok(data) => match context.get("data").cloned() {
+                // the manifest `data` values take precedence over the data files
+                Some(mut value) if value.is_object() => {
+                    merge_data(&mut value, data);
+                    context.insert("data", &value);
+                }
+                Some(_) => {
+                    let warning =
+                        "`data` is not a table, data files are not available to templates"
+                            .to_string();
+                    log_warn!("Data", "{warning}");
+                    self.sink.warning(warning);
+                }
+                None => context.insert("data", &data),
+            },
+            Err(err) if self.ctx.options.server => {
+                log_error!("{err}");
+                self.sink.error(BuildError::new(&err.to_string()));
+            }
+            Err(err) => return Err(err),
+        }
 
         let sort_object = SortObject {};
         let markdown_filter = Markdown {};
 
-        let include_file = IncludeFile::new(project_folder.clone(), dir, context.clone());
+        let include_file = IncludeFile::new(
+            templates_folder.clone(),
+            self.ctx.shared_templates.clone(),
+            dir,
+            context.clone(),
+        );
 
-        let templates_folder = project_folder.join("templates");
         let log = Log {};
 
         tera.register_filter("sort_object", sort_object);
@@ -287,21 +536,48 @@ impl Builder {
             },
         );
         let templates_folder_ = templates_folder.clone();
+        let project_folder_ = self.ctx.project_folder.clone();
         tera.register_function(
             "read_md_files",
             move |args: &HashMap<String, tera::Value>| -> tera::Result<tera::Value> {
-                let value = read_md_files(&templates_folder_, args)?;
+                let value = read_md_files(&templates_folder_, &project_folder_, args)?;
                 Ok(value)
             },
         );
         let templates_folder_ = templates_folder.clone();
+        let project_folder_ = self.ctx.project_folder.clone();
         tera.register_function(
             "read_md_file",
             move |args: &HashMap<String, tera::Value>| -> tera::Result<tera::Value> {
-                let value = read_md_file(&templates_folder_, args)?;
+                let value = read_md_file(&templates_folder_, &project_folder_, args)?;
                 Ok(value)
             },
         );
+        let project_folder_ = self.ctx.project_folder.clone();
+        tera.register_function(
+            "load_data",
+            move |args: &HashMap<String, tera::Value>| -> tera::Result<tera::Value> {
+                let path = project_folder_.join(get_arg("path", args)?);
+                let format = if args.contains_key("format") {
+                    DataFormat::from_name(&get_arg("format", args)?)
+                } else {
+                    DataFormat::from_path(&path).ok_or_else(|| {
+                        format!(
+                            "Unable to detect the format of `{}`, please provide the `format` argument",
+                            path.display()
+                        )
+                        .into()
+                    })
+                };
+                let value = format
+                    .and_then(|format| load_data(&path, format))
+                    .map_err(|err| err.to_string())?;
+                Ok(value)
+            },
+        );
+
+        self.sink
+            .scan_templates(&self.ctx, tera.get_template_names());
 
         log_trace!("Render", "processing folders");
 
@@ -338,7 +614,7 @@ impl Builder {
             let mut list = Vec::new();
 
             for locale in languages {
-                let url_prefix = format!("/{locale}/");
+                let url_prefix = format!("{}{locale}/", self.ctx.base_path);
                 let name = match self.i18n_dict.language(locale) {
                     Ok(name) => {
                         if let Some(name) = name {
@@ -369,7 +645,7 @@ impl Builder {
             list
         } else {
             vec![(
-                "/".to_string(),
+                self.ctx.base_path.clone(),
                 None,
                 Language {
                     name: "English".to_string(),
@@ -380,6 +656,45 @@ impl Builder {
 
         context.insert("languages", &language_list);
 
+        let md_tpl_file = &settings.markdown.clone().unwrap_or(".md.html".to_string());
+        let default_dm_template_path = self.ctx.template_file(md_tpl_file);
+        let mut default_dm_template = None;
+        if default_dm_template_path.exists() {
+            default_dm_template = Some(md_tpl_file);
+        }
+
+        self.collect_pages(
+            &tera,
+            exclude,
+            &sections,
+            &info,
+            default_dm_template.is_some(),
+        );
+        let collection = self.collection.clone();
+        tera.register_function(
+            "get_page",
+            move |args: &HashMap<String, tera::Value>| -> tera::Result<tera::Value> {
+                let path = get_arg("path", args)?;
+                let lang = args.get("lang").and_then(|lang| lang.as_str());
+                match collection.lock().unwrap().page(&path, lang) {
+                    Some(page) => Ok(tera::to_value(page)?),
+                    None => Err(format!("get_page: page `{path}` not found").into()),
+                }
+            },
+        );
+        let collection = self.collection.clone();
+        tera.register_function(
+            "get_section",
+            move |args: &HashMap<String, tera::Value>| -> tera::Result<tera::Value> {
+                let name = get_arg("name", args)?;
+                let lang = args.get("lang").and_then(|lang| lang.as_str());
+                match collection.lock().unwrap().section(&name, lang) {
+                    Some(section) => Ok(tera::to_value(section)?),
+                    None => Err(format!("get_section: section `{name}` not found").into()),
+                }
+            },
+        );
+
         /*
         for (_, language, _) in &info {
             let path = if let Some(language) = language {
@@ -426,47 +741,66 @@ impl Builder {
         let tera_ = tera.clone();
         let mut context_ = context.clone();
 
-        let mut render_file =
-            move |template: String, destination: String, args: &HashMap<String, tera::Value>| {
-                log_trace!(
-                    "RenderFile",
-                    "{} {} => {}",
-                    style("render_file:").cyan(),
-                    template,
-                    destination
+        let mut render_file = move |template: String,
+                                    destination: String,
+                                    args: &HashMap<String, tera::Value>,
+                                    sources: &[PathBuf]| {
+            let mut sources = sources.to_vec();
+            sources.push(this.ctx.template_file(&template));
+
+            log_trace!(
+                "RenderFile",
+                "{} {} => {}",
+                style("render_file:").cyan(),
+                template,
+                destination
+            );
+            for (url_prefix, folder, language) in &info_ {
+                context_.extend(tera::Context::from_serialize(args).unwrap());
+                let content = this.render_template(
+                    &tera_,
+                    &template,
+                    &mut context_,
+                    language,
+                    url_prefix,
+                    &destination,
                 );
-                for (url_prefix, folder, language) in &info_ {
-                    context_.extend(tera::Context::from_serialize(args).unwrap());
-                    let content = this.render_template(
-                        &tera_,
-                        &template,
-                        &mut context_,
-                        language,
-                        url_prefix,
-                    );
 
-                    let this_ = this.clone();
-                    if let Ok(content) = content {
-                        let template_ = template.clone();
-                        let destination_ = destination.clone();
-                        let folder_ = folder.clone();
-                        this_
-                            .save_file(&content, &destination_, folder_.as_ref())
-                            .map_err(|err| {
-                                log_warn!(
-                                    "RenderFile",
-                                    "Unable to render template: {template_}, error: {err:?}"
-                                );
-                            })
-                            .ok();
-                    } else {
-                        log_warn!(
-                            "RenderFile",
-                            "Unable to render template: {template}, error: {content:?}"
-                        );
+                let this_ = this.clone();
+                if let Ok(content) = content {
+                    let template_ = template.clone();
+                    let destination_ = destination.clone();
+                    let folder_ = folder.clone();
+                    this_
+                        .save_file(
+                            content.as_deref(),
+                            &destination_,
+                            folder_.as_ref(),
+                            &sources,
+                        )
+                        .map_err(|err| {
+                            log_warn!(
+                                "RenderFile",
+                                "Unable to render template: {template_}, error: {err:?}"
+                            );
+                            this_.sink.warning(format!(
+                                "Unable to render template: {template_}, error: {err}"
+                            ));
+                        })
+                        .ok();
+                } else {
+                    log_warn!(
+                        "RenderFile",
+                        "Unable to render template: {template}, error: {content:?}"
+                    );
+                    if let Err(err) = content {
+                        this.sink.warning(format!(
+                            "Unable to render template: {template}, error: {err}"
+                        ));
                     }
                 }
-            };
+            }
+        };
 
         let mut render_file_ = render_file.clone();
 
@@ -478,7 +812,7 @@ impl Builder {
                         let template = get_arg("file", args)?;
                         let destination = get_arg("dest", args)?;
 
-                        render_file_(template, destination, args);
+                        render_file_(template, destination, args, &[]);
                         Ok(tera::Value::Bool(true))
                     },
                 )),
@@ -487,72 +821,67 @@ impl Builder {
 
         log_trace!("Render", "rendering");
 
-        let md_tpl_file = &settings.markdown.clone().unwrap_or(".md.html".to_string());
-        let default_dm_template_path = templates_folder.join(md_tpl_file);
-        let mut default_dm_template = None;
-        if default_dm_template_path.exists() {
-            default_dm_template = Some(md_tpl_file);
-        }
-
-        for template in tera.get_template_names() {
-            let root_folder = match root_folder(template) {
-                Some(folder) => folder,
-                None => {
-                    continue;
+        let mut deprecated = AHashSet::new();
+        for section in sections.values().flat_map(|section| section.walk()) {
+            if let Some(template) = &section.template_file {
+                let template_file = self.ctx.template_file(template);
+                let uses_section_file = std::fs::read_to_string(&template_file)
+                    .is_ok_and(|text| text.contains("section_file"));
+                if uses_section_file && deprecated.insert(template.clone()) {
+                    let warning = format!(
+                        "`{template}`: `section_file` is deprecated and will be removed, use `page.content` instead"
+                    );
+                    log_warn!("Render", "{warning}");
+                    self.sink.warning(warning);
                 }
-            };
-
-            let mut destination = template.to_string();
-
-            let section = sections.get(&root_folder);
-
-            //println!("root_folder: {root_folder} template: {template}");
-            if let Some(section) = section {
-                if section.template_file.ends_with(template) {
-                    let sep = format!("/{}/", section.name);
-                    for file in &section.files {
-                        context.insert("section_file", file);
-                        let mut parts = file.split(&sep);
-                        parts.next();
-                        let destination = match parts.next() {
-                            Some(value) => PathBuf::from(&format!("{}/{}", section.name, value))
-                                .with_extension("html"),
-                            None => continue,
-                        };
-                        //println!("destination: {destination:?}");
-                        //println!("section_file: {file:?}");
-
-                        for (url_prefix, folder, language) in &info {
-                            let content = self.render_template(
-                                &tera,
-                                template,
-                                &mut context,
-                                language,
-                                url_prefix,
-                            )?;
-                            self.save_file(
-                                &content,
-                                destination.to_str().unwrap(),
-                                folder.as_ref(),
-                            )?;
-                        }
+                // the file is exposed to the template as `page`
+                for file in &section.files {
+                    context.insert("section_file", &file.include);
+                    for (url_prefix, folder, language) in &info {
+                        let content = self.render_template(
+                            &tera,
+                            template,
+                            &mut context,
+                            language,
+                            url_prefix,
+                            &file.destination,
+                        )?;
+                        self.save_file(
+                            content.as_deref(),
+                            &file.destination,
+                            folder.as_ref(),
+                            &[template_file.clone(), file.file.clone()],
+                        )?;
                     }
-                    continue;
                 }
-                if is_file_hidden(template) {
-                    continue;
-                }
-                destination =
-                    template.replace(&format!("{root_folder}/"), &format!("{}/", section.name));
-                //println!("destination: {destination}");
-            } else if is_hidden(template) {
-                continue;
+                context.remove("section_file");
             }
 
-            if exclude.is_match(template) {
-                log_trace!("Render", "{} `{}`", style("ignore:").yellow(), template);
-                continue;
+            if let (Some(index), Some(destination)) =
+                (&section.index_file, section.index_destination())
+            {
+                // the index lists the pages of the section
+                let mut sources = vec![self.ctx.template_file(index)];
+                sources.extend(section.files.iter().map(|file| file.file.clone()));
+                for (url_prefix, folder, language) in &info {
+                    let content = self.render_template(
+                        &tera,
+                        index,
+                        &mut context,
+                        language,
+                        url_prefix,
+                        &destination,
+                    )?;
+                    self.save_file(content.as_deref(), &destination, folder.as_ref(), &sources)?;
+                }
             }
+        }
+
+        for template in tera.get_template_names() {
+            let destination = match page_destination(template, &sections, exclude) {
+                Some(destination) => destination,
+                None => continue,
+            };
 
             if template.ends_with(".md") {
                 if default_dm_template.is_none() {
@@ -575,36 +904,439 @@ impl Builder {
                 );
                 args.insert("file".to_string(), file_name.replace(".md", "").into());
 
-                render_file(md_template.to_string(), destination, &args);
+                render_file(
+                    md_template.to_string(),
+                    destination,
+                    &args,
+                    &[self.ctx.template_file(template)],
+                );
             } else {
                 for (url_prefix, folder, language) in &info {
-                    let content =
-                        self.render_template(&tera, template, &mut context, language, url_prefix)?;
-                    self.save_file(&content, &destination, folder.as_ref())?;
+                    let content = self.render_template(
+                        &tera,
+                        template,
+                        &mut context,
+                        language,
+                        url_prefix,
+                        &destination,
+                    )?;
+                    self.save_file(
+                        content.as_deref(),
+                        &destination,
+                        folder.as_ref(),
+                        &[self.ctx.template_file(template)],
+                    )?;
                 }
             }
         }
 
         //println!("context: {:#?}", context.into_json());
 
-        if self.ctx.options.server {
-            let update_json_file = self.ctx.site_folder.join("__wahoo.json");
-            std::fs::write(update_json_file, "{}").ok();
+        // export dev server rules for static hosting providers
+        // (`wahoo serve` applies them itself)
+        let serve = (!self.ctx.options.server)
+            .then_some(self.ctx.manifest.serve.as_ref())
+            .flatten();
+        if let Some(serve) = serve {
+            let rules = Rules::try_new(serve)?;
+            if !rules.is_empty() {
+                let headers = rules.headers_file();
+                if !headers.is_empty() {
+                    self.sink
+                        .write(&self.ctx, Path::new("_headers"), headers.as_bytes())?;
+                }
+                let redirects = rules.redirects_file();
+                if !redirects.is_empty() {
+                    self.sink
+                        .write(&self.ctx, Path::new("_redirects"), redirects.as_bytes())?;
+                }
+            }
         }
 
         Ok(())
     }
 
+    /// Collect the page index exposed to templates as `pages`,
+    /// `get_page()` and `get_section()`
+    fn collect_pages(
+        &self,
+        tera: &tera::Tera,
+        exclude: &Filter,
+        sections: &HashMap<String, SectionInfo>,
+        info: &[(String, Option<String>, Language)],
+        markdown: bool,
+    ) {
+        let mut sources = vec![];
+        for template in tera.get_template_names() {
+            if !(template.ends_with(".html") || (markdown && template.ends_with(".md"))) {
+                continue;
+            }
+            let Some(destination) = page_destination(template, sections, exclude) else {
+                continue;
+            };
+            let section = root_folder(template)
+                .and_then(|folder| sections.get(&folder))
+                .map(|section| section.name.clone());
+            sources.push(PageSource {
+                path: template.to_string(),
+                file: self.ctx.template_file(template),
+                destination: Path::new(&destination)
+                    .with_extension("html")
+                    .to_string_lossy()
+                    .replace('\\', "/"),
+                section,
+                slug: None,
+                content: false,
+                template: None,
+            });
+        }
+
+        let mut section_sources = vec![];
+        for section in sections.values().flat_map(|section| section.walk()) {
+            section_sources.push(SectionSource {
+                name: section.name.clone(),
+                title: section.title.clone(),
+                index: section.index_destination(),
+            });
+            if section.template_file.is_none() {
+                continue;
+            }
+            for file in section.files.iter() {
+                sources.push(PageSource {
+                    path: file.path.clone(),
+                    file: file.file.clone(),
+                    destination: file.destination.clone(),
+                    section: Some(section.name.clone()),
+                    slug: Some(file.slug.clone()),
+                    content: true,
+                    template: file.path.ends_with(".html").then(|| file.template()),
+                });
+            }
+        }
+
+        let languages = info
+            .iter()
+            .map(|(url_prefix, _, language)| (url_prefix.clone(), language.locale.clone()))
+            .collect::<Vec<_>>();
+        let default_locale = languages
+            .iter()
+            .map(|(_, locale)| locale.as_str())
+            .find(|locale| *locale == "en")
+            .or_else(|| languages.first().map(|(_, locale)| locale.as_str()))
+            .unwrap_or("en");
+
+        let mut warnings = vec![];
+        let collection = Collection::build(
+            &sources,
+            section_sources,
+            &languages,
+            default_locale,
+            &mut warnings,
+        );
+        for warning in warnings {
+            log_warn!("Pages", "{warning}");
+            self.sink.warning(warning);
+        }
+        let files = sources
+            .iter()
+            .map(|source| source.file.clone())
+            .collect::<Vec<_>>();
+        self.sink.collected(&files);
+        *self.collection.lock().unwrap() = collection;
+    }
+
+    /// Load the section `name` along with its subsections (subfolders holding
+    /// a `_section.toml` file); sections without `enumerate` or `index`
+    /// only provide template data
+    async fn load_section(
+        &self,
+        name: &str,
+        section: &Section,
+        settings: &Settings,
+    ) -> Result<Option<SectionInfo>> {
+        let section_settings = match &section.settings {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let enumerate = section_settings.enumerate.unwrap_or(false);
+        if !enumerate && section_settings.index.is_none() {
+            return Ok(None);
+        }
+
+        let folder = section_settings
+            .folder
+            .as_ref()
+            .ok_or_else(|| format!("Section `{name}`: `folder` is not set"))?;
+        let section_folder = self.ctx.project_folder.join(folder);
+        if !section_folder.is_dir() {
+            return Err(format!(
+                "Section `{name}`: folder `{}` not found",
+                section_folder.display()
+            )
+            .into());
+        }
+
+        // markdown layout used by sections enumerating files without a template
+        let layout = settings.markdown.clone().unwrap_or(".md.html".to_string());
+        let mut sections = vec![(
+            PathBuf::new(),
+            SectionInfo {
+                name: name.to_string(),
+                title: section_settings.title.clone(),
+                template_file: enumerate.then(|| {
+                    section_settings
+                        .template
+                        .clone()
+                        .unwrap_or_else(|| layout.clone())
+                }),
+                index_file: section_settings.index.clone(),
+                permalink: section_settings
+                    .permalink
+                    .clone()
+                    .unwrap_or(DEFAULT_PERMALINK.to_string()),
+                slugify: section_settings.slugify.unwrap_or(false),
+                files: vec![],
+                subsections: vec![],
+            },
+        )];
+
+        // subfolders holding a `_section.toml` file are subsections
+        for entry in WalkDir::new(&section_folder)
+            .min_depth(1)
+            .sort_by_file_name()
+            .into_iter()
+            .flatten()
+        {
+            let relative = entry.path().strip_prefix(&section_folder).unwrap();
+            if !entry.file_type().is_dir()
+                || is_hidden(relative)
+                || !entry.path().join(SUBSECTION_FILE).is_file()
+            {
+                continue;
+            }
+            let mut warnings = vec![];
+            let toml = Manifest::load_toml(
+                entry.path(),
+                SUBSECTION_FILE,
+                FileKind::Section,
+                &mut warnings,
+            )
+            .await?;
+            for warning in warnings {
+                self.sink.warning(warning);
+            }
+            let settings: SectionSettings = match toml.get("settings") {
+                Some(settings) => settings.clone().try_into()?,
+                None => SectionSettings::default(),
+            };
+
+            // subsections inherit the settings of their parent
+            let (_, parent) = sections
+                .iter()
+                .filter(|(folder, _)| relative.starts_with(folder))
+                .max_by_key(|(folder, _)| folder.components().count())
+                .unwrap();
+            let template = match settings.enumerate {
+                Some(false) => None,
+                Some(true) => Some(
+                    settings
+                        .template
+                        .or_else(|| parent.template_file.clone())
+                        .unwrap_or_else(|| layout.clone()),
+                ),
+                None => settings.template.or_else(|| parent.template_file.clone()),
+            };
+            let subsection = SectionInfo {
+                name: format!("{name}/{}", relative.to_string_lossy().replace('\\', "/")),
+                title: settings.title,
+                template_file: template,
+                index_file: settings.index.or_else(|| parent.index_file.clone()),
+                permalink: settings
+                    .permalink
+                    .unwrap_or_else(|| parent.permalink.clone()),
+                slugify: settings.slugify.unwrap_or(parent.slugify),
+                files: vec![],
+                subsections: vec![],
+            };
+            sections.push((relative.to_path_buf(), subsection));
+        }
+
+        for (_, section) in sections.iter() {
+            for template in [&section.template_file, &section.index_file]
+                .into_iter()
+                .flatten()
+            {
+                if !template.ends_with(".html") {
+                    return Err(format!(
+                        "Section `{}`: template `{template}` must be an `.html` file",
+                        section.name
+                    )
+                    .into());
+                }
+                if !self.ctx.template_file(template).is_file() {
+                    return Err(format!(
+                        "Section `{}`: template `{template}` not found",
+                        section.name
+                    )
+                    .into());
+                }
+            }
+        }
+
+        // files belong to their deepest subsection
+        let mut destinations = HashMap::new();
+        for entry in WalkDir::new(&section_folder)
+            .sort_by_file_name()
+            .into_iter()
+            .flatten()
+        {
+            let path = entry.path();
+            let relative = path.strip_prefix(&section_folder).unwrap();
+            let r = relative.to_str().unwrap();
+            if !entry.file_type().is_file()
+                || !(r.ends_with(".md") || r.ends_with(".html"))
+                || is_hidden(relative)
+            {
+                continue;
+            }
+
+            let (folder, section) = sections
+                .iter_mut()
+                .filter(|(folder, _)| relative.starts_with(folder))
+                .max_by_key(|(folder, _)| folder.components().count())
+                .unwrap();
+            let file = self.section_file(section, path, relative.strip_prefix(folder).unwrap())?;
+            if let Some(other) = destinations.insert(file.destination.clone(), file.path.clone()) {
+                return Err(format!(
+                    "Section `{name}`: `{other}` and `{}` are both rendered to `{}`",
+                    file.path, file.destination
+                )
+                .into());
+            }
+            section.files.push(file);
+        }
+
+        let mut sections = sections.into_iter().map(|(_, section)| section);
+        let mut section = sections.next().unwrap();
+        section.subsections = sections.collect();
+        Ok(Some(section))
+    }
+
+    /// Section `file` located at `relative` in the `section` folder
+    fn section_file(
+        &self,
+        section: &SectionInfo,
+        file: &Path,
+        relative: &Path,
+    ) -> Result<SectionFile> {
+        let path = normalize_path(file);
+        let slug = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|text| {
+                front_matter(&text, relative.extension().is_some_and(|ext| ext == "md")).ok()
+            })
+            .and_then(|front_matter| {
+                front_matter
+                    .get("slug")
+                    .and_then(|slug| slug.as_str())
+                    .map(String::from)
+            })
+            .unwrap_or_else(|| {
+                let stem = relative.file_stem().unwrap().to_string_lossy();
+                if section.slugify {
+                    slugify(&stem)
+                } else {
+                    stem.to_string()
+                }
+            });
+        let folder = match relative.parent() {
+            Some(folder) if folder != Path::new("") => {
+                format!("{}/", folder.to_string_lossy().replace('\\', "/"))
+            }
+            _ => String::new(),
+        };
+        let destination = section
+            .permalink
+            .replace("{section}", &section.name)
+            .replace("{path}", &folder)
+            .replace("{slug}", &slug);
+        let project_path = path
+            .strip_prefix(&self.ctx.project_folder)
+            .unwrap_or(&path)
+            .to_string_lossy()
+            .replace('\\', "/");
+
+        // the output path must stay within the language folder
+        let mut parts = vec![];
+        for component in Path::new(&destination).components() {
+            match component {
+                Component::Normal(part) => parts.push(part.to_string_lossy().to_string()),
+                Component::CurDir => {}
+                _ => {
+                    return Err(format!(
+                        "Section `{}`: `{project_path}` is rendered to `{destination}`, outside of the site folder",
+                        section.name
+                    )
+                    .into())
+                }
+            }
+        }
+        if parts.is_empty() {
+            return Err(format!(
+                "Section `{}`: `{project_path}` has an empty output path",
+                section.name
+            )
+            .into());
+        }
+
+        let include = match (
+            self.ctx
+                .templates_folder
+                .strip_prefix(&self.ctx.project_folder),
+            path.strip_prefix(&self.ctx.project_folder),
+        ) {
+            (Ok(templates), Ok(_)) => format!(
+                "{}{project_path}",
+                "../".repeat(templates.components().count())
+            ),
+            _ => path.to_string_lossy().replace('\\', "/"),
+        };
+
+        Ok(SectionFile {
+            include,
+            path: project_path,
+            file: path,
+            slug,
+            destination: parts.join("/"),
+        })
+    }
+
     pub async fn execute(&self) -> Result<()> {
         // if !self.options.serve
         self.sink.init(&self.ctx).await?;
+        self.sink.begin();
+        for warning in self.ctx.manifest.warnings.iter() {
+            self.sink.warning(warning.clone());
+        }
+        for key in RESERVED_KEYS {
+            if self.ctx.manifest.toml.get(key).is_some() {
+                let warning = format!(
+                    "manifest key `{key}` is shadowed by the `{key}` template variable and is not available to templates"
+                );
+                log_warn!("Manifest", "{warning}");
+                self.sink.warning(warning);
+            }
+        }
         // if sink.is_none() {
         //     self.ctx.clean().await?;
         // }
         // self.ctx.ensure_folders().await?;
 
-        let glob = "templates/**/*{.html,.md,.js,.raw}";
-        let include = Filter::new(&[glob]);
+        // templates located in the source folder are not migrated
+        let include = match self.ctx.templates_folder.strip_prefix(&self.ctx.src_folder) {
+            Ok(relative) => Filter::new(&[relative.join(TEMPLATES_GLOB).to_str().unwrap()]),
+            Err(_) => Filter::default(),
+        };
 
         let settings = self.ctx.settings();
 
@@ -625,69 +1357,32 @@ impl Builder {
 
             let mut section_exclude_list = vec![];
             for (name, section) in sections {
-                let section_settings = match &section.settings {
-                    Some(value) => value,
-                    None => continue,
-                };
-
-                let enumerate = match section_settings.enumerate {
-                    Some(value) => value,
-                    None => continue,
-                };
-
-                if !enumerate {
-                    continue;
-                }
-
-                let template = match &section_settings.template {
-                    Some(value) => value,
+                let section_info = match self.load_section(name, section, &settings).await? {
+                    Some(section_info) => section_info,
                     None => continue,
                 };
 
-                let index_file = match &section_settings.index {
-                    Some(value) => value,
-                    None => continue,
-                };
-
-                if !template.ends_with(".html") || !index_file.ends_with(".html") {
-                    continue;
-                }
-
-                let folder = match &section_settings.folder {
-                    Some(value) => value,
-                    None => continue,
-                };
-
-                let files = WalkDir::new(self.ctx.project_folder.join(folder))
+                // templates located in a folder render the section pages
+                let folder = [&section_info.template_file, &section_info.index_file]
                     .into_iter()
                     .flatten()
-                    .filter_map(|entry| {
-                        let path = entry.path();
-                        let relative = path.strip_prefix(&self.ctx.project_folder).unwrap();
-
-                        let r = relative.to_str().unwrap();
-                        if !(r.ends_with(".md") || r.ends_with(".html")) || is_hidden(relative) {
-                            return None;
+                    .find(|template| Path::new(template).components().count() > 1)
+                    .and_then(root_folder)
+                    .unwrap_or_else(|| name.clone());
+                for section in section_info.walk() {
+                    for file in [&section.template_file, &section.index_file]
+                        .into_iter()
+                        .flatten()
+                    {
+                        if !section_exclude_list.contains(file) {
+                            section_exclude_list.push(file.clone());
                         }
-
-                        //let _is_dir = entry.file_type().is_dir();
-
-                        Some(format!("../../{}", relative.to_str().unwrap()))
-                    })
-                    .collect::<Vec<String>>();
-
-                let folder = root_folder(template).unwrap();
-                let section_info = SectionInfo {
-                    name: name.clone(),
-                    template_file: template.clone(),
-                    files,
-                };
-
-                section_exclude_list.push(template);
+                    }
+                }
                 section_infos.insert(folder, section_info);
             }
 
-            for a in section_exclude_list {
+            for a in section_exclude_list.iter() {
                 exclude_list.push(a);
             }
 
@@ -698,10 +1393,12 @@ impl Builder {
 
         let render_start = Instant::now();
         log_trace!("Migrate", "migrating files");
+        self.sink.progress("migrate");
         self.migrate(&include, &exclude).await?;
         log_trace!("Render", "loading templates");
-        self.render(glob, &exclude, &settings, section_infos)
-            .await?;
+        self.sink.progress("render");
+        self.render(&exclude, &settings, section_infos).await?;
+        self.sink.finish(&self.ctx);
 
         let duration = render_start.elapsed();
         log_info!(
@@ -712,7 +1409,19 @@ impl Builder {
 
         let package_json = self.ctx.site_folder.join("package.json");
         let node_modules = self.ctx.site_folder.join("node_modules");
-        if package_json.is_file() && !node_modules.is_dir() {
+        if self.sink.is_memory() {
+            // in-memory builds serve `node_modules` from the site folder
+            if self.sink.exists(&self.ctx, Path::new("package.json")) && !node_modules.is_dir() {
+                log_warn!(
+                    "NPM",
+                    "detected `package.json`; run `wahoo build` to install dependencies"
+                );
+                self.sink.warning(
+                    "detected `package.json`; run `wahoo build` to install dependencies"
+                        .to_string(),
+                );
+            }
+        } else if package_json.is_file() && !node_modules.is_dir() {
             log_info!("NPM", "detected `package.json`; installing ... ");
             println!();
             cmd!("npm", "install").dir(&self.ctx.site_folder).run()?;
@@ -727,12 +1436,13 @@ impl Builder {
         tera: &mut tera::Tera,
         context: &mut tera::Context,
     ) -> Result<()> {
+        let base_path = &self.ctx.base_path;
         tera.add_raw_template(
             "__INDEX__.html",
-            "<!DOCTYPE html><html lang=\"en-gb\"><head><script>window.location.href=\"/en/index.html\";</script></head><body>Please wait. Redirecting...</body></html>"
+            &format!("<!DOCTYPE html><html lang=\"en-gb\"><head><script>window.location.href=\"{base_path}en/index.html\";</script></head><body>Please wait. Redirecting...</body></html>")
         )?;
 
-        let url_prefix = "/en/".to_string();
+        let url_prefix = format!("{base_path}en/");
 
         let language = Language {
             name: "English".to_string(),
@@ -740,8 +1450,8 @@ impl Builder {
         };
 
         let content =
-            self.render_template(tera, "__INDEX__.html", context, &language, &url_prefix)?;
-        self.save_file(&content, "index.html", None)?;
+            self.render_template(tera, "__INDEX__.html", context, &language, &url_prefix, "")?;
+        self.save_file(content.as_deref(), "index.html", None, &[])?;
 
         Ok(())
     }
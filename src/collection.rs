@@ -0,0 +1,502 @@
+use crate::prelude::*;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde_json::Value;
+
+static FRONT_MATTER: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?s)<!---toml(.*?)-->").unwrap());
+static TAGS: Lazy<Regex> = Lazy::new(|| Regex::new(r"<[^>]*>").unwrap());
+static HEADINGS: Lazy<[Regex; 2]> = Lazy::new(|| {
+    [
+        Regex::new("(?s)<title[^>]*>(.*?)</title>").unwrap(),
+        Regex::new("(?s)<h1[^>]*>(.*?)</h1>").unwrap(),
+    ]
+});
+static SECTION_HEADINGS: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?s)<h([1-6])>(.*?)</h[1-6]>").unwrap());
+static MARKUP: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?s)<!--.*?-->|<script.*?</script>|<style.*?</style>|\{\{.*?\}\}|\{%.*?%\}|\{#.*?#\}|<[^>]*>").unwrap()
+});
+
+/// A page rendered from a template or a section file
+#[derive(Debug, Clone, Serialize)]
+pub struct Page {
+    /// template path relative to the templates folder, or section
+    /// file path relative to the project folder
+    pub path: String,
+    pub url: String,
+    /// front matter `slug` or file name of a section file
+    pub slug: Option<String>,
+    /// front matter `title`, otherwise the first heading (or html `<title>`)
+    pub title: Option<String>,
+    /// `<!---toml ... -->` front matter (`null` if none)
+    pub front_matter: Value,
+    /// locale the page is rendered for
+    pub lang: String,
+    pub word_count: usize,
+    /// name of the section the page belongs to (`<section>/<subsection>`
+    /// for pages of a subsection)
+    pub section: Option<String>,
+    /// rendered html of a section file; html section files are rendered
+    /// through Tera with the page context, so their `content` is only
+    /// available to the template rendering the page itself
+    pub content: Option<String>,
+    /// headings of a section file
+    pub toc: Vec<TocEntry>,
+    /// template rendering the `content` of an html section file
+    #[serde(skip)]
+    pub template: Option<String>,
+}
+
+/// Table of contents entry of a section file, linking to the heading `id`
+#[derive(Debug, Clone, Serialize)]
+pub struct TocEntry {
+    pub level: usize,
+    pub id: String,
+    pub title: String,
+}
+
+/// Source of the pages rendered for each language
+#[derive(Debug, Clone)]
+pub struct PageSource {
+    pub path: String,
+    pub file: PathBuf,
+    /// output path relative to the language folder
+    pub destination: String,
+    pub section: Option<String>,
+    pub slug: Option<String>,
+    /// render the page `content` and `toc` (section files)
+    pub content: bool,
+    pub template: Option<String>,
+}
+
+/// A section or subsection of the page index
+#[derive(Debug, Clone)]
+pub struct SectionSource {
+    /// section name, followed by the subsection folders (i.e. `blog/2024`)
+    pub name: String,
+    pub title: Option<String>,
+    /// output path of the section index page, relative to the language folder
+    pub index: Option<String>,
+}
+
+/// Section returned by `get_section()` and exposed as `section` to index templates
+#[derive(Debug, Clone, Serialize)]
+pub struct SectionPages {
+    pub name: String,
+    pub title: Option<String>,
+    /// url of the section index page
+    pub url: Option<String>,
+    /// pages sorted by `weight`, then by `date` (newest first)
+    pub pages: Vec<Page>,
+    pub subsections: Vec<SectionPages>,
+}
+
+/// `breadcrumbs` entry, from the site root to the rendered page
+#[derive(Debug, Clone, Serialize)]
+pub struct Breadcrumb {
+    pub title: String,
+    pub url: Option<String>,
+}
+
+/// Index of the pages of a build, collected before rendering
+#[derive(Debug, Clone, Default)]
+pub struct Collection {
+    pages: Vec<Page>,
+    /// page url => index of the page in `pages`
+    urls: HashMap<String, usize>,
+    /// locale => `pages` template variable, without the `content` of section files
+    listings: HashMap<String, tera::Value>,
+    sections: Vec<SectionSource>,
+    /// section index page url => section name
+    section_urls: HashMap<String, String>,
+    /// `(url prefix, locale)` of each language
+    languages: Vec<(String, String)>,
+    /// locale used when `get_page()` and `get_section()` are called without `lang`
+    default_locale: String,
+}
+
+impl Collection {
+    /// Collect the pages of `sources` for each `(url prefix, locale)` language;
+    /// unreadable files and invalid front matter are reported as warnings
+    pub fn build(
+        sources: &[PageSource],
+        sections: Vec<SectionSource>,
+        languages: &[(String, String)],
+        default_locale: &str,
+        warnings: &mut Vec<String>,
+    ) -> Collection {
+        let mut pages = vec![];
+        for source in sources.iter() {
+            let text = match std::fs::read_to_string(&source.file) {
+                Ok(text) => text,
+                Err(err) => {
+                    warnings.push(format!(
+                        "Unable to read page `{}`: {err}",
+                        source.file.display()
+                    ));
+                    continue;
+                }
+            };
+
+            let markdown = source.path.ends_with(".md");
+            let front_matter = match front_matter(&text, markdown) {
+                Ok(front_matter) => front_matter,
+                Err(err) => {
+                    warnings.push(format!(
+                        "Invalid front matter in `{}`: {err}",
+                        source.file.display()
+                    ));
+                    Value::Null
+                }
+            };
+            let title = front_matter
+                .get("title")
+                .and_then(|title| title.as_str())
+                .map(String::from)
+                .or_else(|| heading(&text, markdown));
+            let word_count = if markdown {
+                markdown_word_count(&text)
+            } else {
+                html_word_count(&text)
+            };
+            // html section files are rendered along with their page
+            let (content, toc) = if source.content && markdown {
+                let (content, toc) = html_toc(&markdown_to_html(&text, true));
+                (Some(content), toc)
+            } else {
+                (None, vec![])
+            };
+
+            for (url_prefix, locale) in languages.iter() {
+                pages.push(Page {
+                    path: source.path.clone(),
+                    url: format!("{url_prefix}{}", source.destination),
+                    slug: source.slug.clone(),
+                    title: title.clone(),
+                    front_matter: front_matter.clone(),
+                    lang: locale.clone(),
+                    word_count,
+                    section: source.section.clone(),
+                    content: content.clone(),
+                    toc: toc.clone(),
+                    template: source.template.clone(),
+                });
+            }
+        }
+        pages.sort_by(|a, b| a.path.cmp(&b.path));
+
+        let urls = pages
+            .iter()
+            .enumerate()
+            .map(|(index, page)| (page.url.clone(), index))
+            .collect();
+
+        let mut listings = HashMap::new();
+        for (_, locale) in languages.iter() {
+            let listing = pages
+                .iter()
+                .filter(|page| &page.lang == locale)
+                .map(|page| Page {
+                    content: None,
+                    ..page.clone()
+                })
+                .collect::<Vec<_>>();
+            let listing = tera::to_value(listing).unwrap_or_default();
+            listings.insert(locale.clone(), listing);
+        }
+
+        let mut section_urls = HashMap::new();
+        for (url_prefix, _) in languages.iter() {
+            for section in sections.iter() {
+                if let Some(index) = &section.index {
+                    section_urls.insert(format!("{url_prefix}{index}"), section.name.clone());
+                }
+            }
+        }
+
+        Collection {
+            pages,
+            urls,
+            listings,
+            sections,
+            section_urls,
+            languages: languages.to_vec(),
+            default_locale: default_locale.to_string(),
+        }
+    }
+
+    /// Pages rendered for `locale`, as exposed to templates (the
+    /// `content` of section files is only available as `page.content`)
+    pub fn pages(&self, locale: &str) -> tera::Value {
+        self.listings
+            .get(locale)
+            .cloned()
+            .unwrap_or_else(|| tera::Value::Array(vec![]))
+    }
+
+    /// Page of a template or section file `path`, or of a page url
+    pub fn page(&self, path: &str, locale: Option<&str>) -> Option<Page> {
+        let locale = locale.unwrap_or(&self.default_locale);
+        if let Some(page) = self.page_at(path).filter(|page| page.lang == locale) {
+            return Some(page.clone());
+        }
+        self.pages
+            .iter()
+            .find(|page| page.lang == locale && page.path == path)
+            .cloned()
+    }
+
+    /// Page rendered at `url`
+    pub fn page_at(&self, url: &str) -> Option<&Page> {
+        self.urls.get(url).map(|index| &self.pages[*index])
+    }
+
+    /// Section or subsection `name` along with its pages and subsections
+    pub fn section(&self, name: &str, locale: Option<&str>) -> Option<SectionPages> {
+        let locale = locale.unwrap_or(&self.default_locale);
+        let section = self.sections.iter().find(|section| section.name == name)?;
+
+        let mut pages = self
+            .pages
+            .iter()
+            .filter(|page| page.lang == locale && page.section.as_deref() == Some(name))
+            .cloned()
+            .collect::<Vec<_>>();
+        pages.sort_by(compare_pages);
+
+        let subsections = self
+            .sections
+            .iter()
+            .filter(|subsection| self.parent_section(&subsection.name) == Some(name))
+            .filter_map(|subsection| self.section(&subsection.name, Some(locale)))
+            .collect();
+
+        Some(SectionPages {
+            name: section.name.clone(),
+            title: section.title.clone(),
+            url: section
+                .index
+                .as_ref()
+                .map(|index| format!("{}{index}", self.url_prefix(locale))),
+            pages,
+            subsections,
+        })
+    }
+
+    /// Breadcrumbs of the page rendered at `url` for `locale`: the site root,
+    /// the sections and subsections containing the page and the page itself
+    pub fn breadcrumbs(&self, url: &str, locale: &str) -> Vec<Breadcrumb> {
+        let url_prefix = self.url_prefix(locale);
+        let home_url = format!("{url_prefix}index.html");
+        let home = self.page_at(&home_url).and_then(|page| page.title.clone());
+        let mut breadcrumbs = vec![Breadcrumb {
+            title: home.unwrap_or_else(|| "Home".to_string()),
+            url: Some(url_prefix.to_string()),
+        }];
+        if url == home_url {
+            return breadcrumbs;
+        }
+
+        if let Some(section) = self.section_at(url) {
+            let mut name = String::new();
+            for folder in section.split('/') {
+                if !name.is_empty() {
+                    name.push('/');
+                }
+                name.push_str(folder);
+                if let Some(section) = self.sections.iter().find(|section| section.name == name) {
+                    breadcrumbs.push(Breadcrumb {
+                        title: section.title.clone().unwrap_or_else(|| folder.to_string()),
+                        url: section
+                            .index
+                            .as_ref()
+                            .map(|index| format!("{url_prefix}{index}")),
+                    });
+                }
+            }
+        }
+
+        if let Some(page) = self.page_at(url) {
+            let title = page.title.clone().unwrap_or_else(|| {
+                Path::new(&page.path)
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().to_string())
+                    .unwrap_or_default()
+            });
+            breadcrumbs.push(Breadcrumb {
+                title,
+                url: Some(page.url.clone()),
+            });
+        }
+        breadcrumbs
+    }
+
+    /// Name of the section of the page or section index page rendered at `url`
+    pub fn section_at(&self, url: &str) -> Option<String> {
+        if let Some(page) = self.page_at(url) {
+            return page.section.clone();
+        }
+        // section index pages are not part of the page list
+        self.section_urls.get(url).cloned()
+    }
+
+    /// Closest section containing the subsection `name`, subsection folders
+    /// being possibly nested in folders which are not subsections
+    fn parent_section(&self, name: &str) -> Option<&str> {
+        self.sections
+            .iter()
+            .map(|section| section.name.as_str())
+            .filter(|parent| {
+                name.strip_prefix(parent)
+                    .is_some_and(|rest| rest.starts_with('/'))
+            })
+            .max_by_key(|parent| parent.len())
+    }
+
+    fn url_prefix(&self, locale: &str) -> &str {
+        self.languages
+            .iter()
+            .find(|(_, language)| language == locale)
+            .map(|(url_prefix, _)| url_prefix.as_str())
+            .unwrap_or("/")
+    }
+}
+
+/// Order pages by ascending `weight`, then by descending `date`
+/// (pages without them last) and then by path
+fn compare_pages(a: &Page, b: &Page) -> std::cmp::Ordering {
+    let weight = |page: &Page| {
+        page.front_matter
+            .get("weight")
+            .and_then(|weight| weight.as_f64())
+    };
+    let date = |page: &Page| {
+        page.front_matter
+            .get("date")
+            .and_then(|date| date.as_str())
+            .map(String::from)
+    };
+    let by_weight = match (weight(a), weight(b)) {
+        (Some(a), Some(b)) => a.total_cmp(&b),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => std::cmp::Ordering::Equal,
+    };
+    let by_date = match (date(a), date(b)) {
+        (Some(a), Some(b)) => b.cmp(&a),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => std::cmp::Ordering::Equal,
+    };
+    by_weight.then(by_date).then_with(|| a.path.cmp(&b.path))
+}
+
+/// Parse the `<!---toml ... -->` front matter of a page
+pub fn front_matter(text: &str, markdown: bool) -> Result<Value> {
+    let toml = if markdown {
+        parse_toml_from_markdown(text)
+    } else {
+        FRONT_MATTER
+            .captures(text)
+            .map(|captures| captures[1].to_string())
+    };
+    match toml {
+        Some(toml) => {
+            let toml: toml::Value = toml::from_str(&toml)?;
+            Ok(datetimes_to_strings(serde_json::to_value(toml)?))
+        }
+        None => Ok(Value::Null),
+    }
+}
+
+/// Replace TOML dates (serialized as objects) by their string representation
+fn datetimes_to_strings(value: Value) -> Value {
+    match value {
+        Value::Object(object) => {
+            if object.len() == 1 {
+                if let Some(Value::String(date)) = object.get("$__toml_private_datetime") {
+                    return Value::String(date.clone());
+                }
+            }
+            Value::Object(
+                object
+                    .into_iter()
+                    .map(|(key, value)| (key, datetimes_to_strings(value)))
+                    .collect(),
+            )
+        }
+        Value::Array(array) => Value::Array(array.into_iter().map(datetimes_to_strings).collect()),
+        value => value,
+    }
+}
+
+/// First markdown `#` heading, or the html `<title>` or `<h1>` text;
+/// headings holding template code are skipped as they are not rendered yet
+fn heading(text: &str, markdown: bool) -> Option<String> {
+    let is_static = |title: &String| {
+        !title.is_empty() && !["{{", "{%", "{#"].iter().any(|tag| title.contains(tag))
+    };
+    if markdown {
+        return text
+            .lines()
+            .filter_map(|line| line.strip_prefix("# "))
+            .map(|title| title.trim().to_string())
+            .find(is_static);
+    }
+
+    HEADINGS
+        .iter()
+        .flat_map(|heading| heading.captures_iter(text))
+        .map(|captures| TAGS.replace_all(&captures[1], "").trim().to_string())
+        .find(is_static)
+}
+
+/// Number of words of an html template, excluding markup and template code
+fn html_word_count(text: &str) -> usize {
+    MARKUP.replace_all(text, " ").split_whitespace().count()
+}
+
+/// Lowercase `text`, replacing runs of other characters than letters and digits with `-`
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+/// Content and headings of a rendered html section file
+pub fn html_content(html: &str) -> (String, Vec<TocEntry>) {
+    html_toc(FRONT_MATTER.replace(html, "").trim())
+}
+
+/// Add an `id` to the `<h1>`..`<h6>` headings of `html` and list them
+fn html_toc(html: &str) -> (String, Vec<TocEntry>) {
+    let mut toc: Vec<TocEntry> = vec![];
+    let html = SECTION_HEADINGS.replace_all(html, |captures: &regex::Captures| {
+        let level = &captures[1];
+        let title = TAGS.replace_all(&captures[2], "").trim().to_string();
+        let slug = match slugify(&title) {
+            slug if slug.is_empty() => "heading".to_string(),
+            slug => slug,
+        };
+        let mut id = slug.clone();
+        let mut n = 1;
+        while toc.iter().any(|entry| entry.id == id) {
+            id = format!("{slug}-{n}");
+            n += 1;
+        }
+        let heading = format!("<h{level} id=\"{id}\">{}</h{level}>", &captures[2]);
+        toc.push(TocEntry {
+            level: level.parse().unwrap(),
+            id,
+            title,
+        });
+        heading
+    });
+    (html.to_string(), toc)
+}
@@ -1,43 +1,137 @@
 use crate::prelude::*;
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
 pub struct Options {
     pub server: bool,
     pub verbose: bool,
+    /// build environment selecting the `[env.<env>]` manifest overlay
+    pub env: String,
+    /// `key.path = value` manifest overrides (`wahoo -D`)
+    pub defines: Vec<(String, String)>,
+    /// output folder overriding `settings.output` (`wahoo --output`)
+    pub output: Option<PathBuf>,
+    /// settings shared by the members of a `[workspace]`
+    pub member: Option<MemberOptions>,
 }
 
+/// Workspace settings applied to a member project
+#[derive(Default, Debug, Clone)]
+pub struct MemberOptions {
+    /// url path the member is served from, i.e. `/docs/`
+    pub base_path: String,
+    /// template folders shared by the workspace members
+    pub templates: Vec<PathBuf>,
+    /// TOML files imported by all workspace members
+    pub imports: Vec<PathBuf>,
+}
+
+/// Default `settings.source` folder, relative to the project folder
+pub const DEFAULT_SOURCE: &str = "src";
+/// Default `settings.templates` folder, relative to the source folder
+pub const DEFAULT_TEMPLATES: &str = "templates";
+/// Default `settings.output` folder, relative to the project folder
+pub const DEFAULT_OUTPUT: &str = "site";
+
 #[derive(Debug)]
 pub struct Context {
     pub manifest: Manifest,
     pub manifest_toml: PathBuf,
     pub site_folder: PathBuf,
     pub src_folder: PathBuf,
+    pub templates_folder: PathBuf,
+    /// template folders of the themes and the workspace, in order of
+    /// precedence, providing templates missing from `templates_folder`
+    pub shared_templates: Vec<PathBuf>,
+    /// theme asset folders providing files missing from `src_folder`
+    pub static_folders: Vec<PathBuf>,
     pub project_folder: PathBuf,
+    /// url path the site is served from (`/` unless a workspace member)
+    pub base_path: String,
     pub options: Options,
 }
 
 impl Context {
     pub async fn create(location: Option<String>, options: Options) -> Result<Context> {
         let manifest_toml = Manifest::locate(location).await?;
+        let manifest = Manifest::load(&manifest_toml, &options).await?;
+        Self::with_manifest(manifest_toml, manifest, options).await
+    }
 
-        let manifest = Manifest::load(&manifest_toml).await?;
+    /// Create the context of a manifest loaded from `manifest_toml`
+    pub async fn with_manifest(
+        manifest_toml: PathBuf,
+        manifest: Manifest,
+        options: Options,
+    ) -> Result<Context> {
         let project_folder = manifest_toml.parent().unwrap().to_path_buf();
 
-        let site_folder = project_folder.join("site");
-        let src_folder = project_folder.join("src");
+        let settings = manifest.settings.clone().unwrap_or_default();
+        let src_folder = normalize_path(
+            &project_folder.join(settings.source.as_deref().unwrap_or(DEFAULT_SOURCE)),
+        );
+        let templates_folder = normalize_path(
+            &src_folder.join(settings.templates.as_deref().unwrap_or(DEFAULT_TEMPLATES)),
+        );
+        let site_folder = if let Some(output) = &options.output {
+            normalize_path(&current_dir().await.join(output))
+        } else {
+            normalize_path(
+                &project_folder.join(settings.output.as_deref().unwrap_or(DEFAULT_OUTPUT)),
+            )
+        };
+
+        // the output folder is cleaned before each build
+        if project_folder.starts_with(&site_folder)
+            || src_folder.starts_with(&site_folder)
+            || site_folder.starts_with(&src_folder)
+        {
+            return Err(format!(
+                "Output folder `{}` must not contain the project or source folder, nor be located inside the source folder",
+                site_folder.display()
+            )
+            .into());
+        }
 
         if options.verbose {
             log_info!("Manifest", "`{}`", manifest_toml.to_str().unwrap());
+            log_info!("Environment", "{}", options.env);
             log_info!("Project", "`{}`", src_folder.to_str().unwrap());
+            log_info!("Templates", "`{}`", templates_folder.to_str().unwrap());
             log_info!("Target", "`{}`", site_folder.to_str().unwrap());
         }
 
+        let mut shared_templates = vec![];
+        let mut static_folders = vec![];
+        for theme in manifest.themes.iter() {
+            if theme.templates_folder().is_dir() {
+                shared_templates.push(theme.templates_folder());
+            }
+            if theme.static_folder().is_dir() {
+                static_folders.push(theme.static_folder());
+            }
+            if options.verbose {
+                log_info!("Theme", "`{}`", theme.folder.to_str().unwrap());
+            }
+        }
+
+        let base_path = match &options.member {
+            Some(member) => {
+                shared_templates.extend(member.templates.iter().cloned());
+                member.base_path.clone()
+            }
+            None => "/".to_string(),
+        };
+
         let ctx = Context {
             manifest,
             manifest_toml,
             project_folder,
             site_folder,
             src_folder,
+            templates_folder,
+            shared_templates,
+            static_folders,
+            base_path,
             options,
         };
 
@@ -86,6 +180,81 @@ impl Context {
         settings.clone()
     }
 
+    /// Files and folders monitored for changes in `serve` mode
+    pub fn watch_targets(&self) -> Vec<PathBuf> {
+        let mut watch_targets = self.manifest.imports.clone();
+        watch_targets.extend([self.manifest_toml.clone(), self.src_folder.clone()]);
+        if !self.templates_folder.starts_with(&self.src_folder) {
+            watch_targets.push(self.templates_folder.clone());
+        }
+        watch_targets.extend(self.shared_templates.iter().cloned());
+        watch_targets.extend(
+            self.manifest
+                .themes
+                .iter()
+                .map(|theme| theme.folder.clone()),
+        );
+
+        let mut folders = vec![];
+        if let Some(Settings {
+            watch: Some(watch), ..
+        }) = &self.manifest.settings
+        {
+            folders.extend(watch.iter().map(|folder| ("watch target", folder)));
+        }
+        if let Some(sections) = &self.manifest.sections {
+            for section in sections.values() {
+                if let Some(SectionSettings {
+                    folder: Some(folder),
+                    ..
+                }) = &section.settings
+                {
+                    folders.push(("section folder", folder));
+                }
+            }
+        }
+
+        if let Some(data) = &self.manifest.data {
+            folders.extend(data.values().map(|file| ("data file", file)));
+        }
+        let data_folder = self.project_folder.join(crate::data::DATA_FOLDER);
+        if data_folder.is_dir() {
+            watch_targets.push(data_folder);
+        }
+
+        for (kind, folder) in folders {
+            let target_folder = self.project_folder.join(folder);
+            match target_folder.canonicalize() {
+                Ok(watch_target) => watch_targets.push(watch_target),
+                Err(err) => {
+                    log_warn!(
+                        "Watch",
+                        "Unable to locate {kind} `{}`: {err}",
+                        target_folder.display()
+                    );
+                }
+            }
+        }
+
+        watch_targets.sort();
+        watch_targets.dedup();
+        watch_targets
+    }
+
+    /// Returns the file of a `template`: the project template if present,
+    /// otherwise the first theme or workspace template providing it
+    pub fn template_file(&self, template: &str) -> PathBuf {
+        let file = self.templates_folder.join(template);
+        if file.is_file() {
+            return file;
+        }
+        self.shared_templates
+            .iter()
+            .map(|folder| folder.join(template))
+            .find(|file| file.is_file())
+            .unwrap_or(file)
+    }
+
     pub fn sections(&self) -> Option<HashMap<String, toml::Value>> {
         if let Some(sections) = self.manifest.sections.as_ref() {
             let mut result = HashMap::new();
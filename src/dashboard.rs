@@ -0,0 +1,118 @@
+use crate::prelude::*;
+use crate::server::{ClientInfo, Server};
+use tide::http::mime;
+use tide::{Body, Response, StatusCode};
+
+const DASHBOARD_HTML: &str = include_str!("./dashboard.html");
+
+/// Dev server state reported by `/__wahoo/api/status`
+#[derive(Debug, Serialize)]
+pub struct Status {
+    pub build: Option<BuildInfo>,
+    pub addresses: Vec<String>,
+    pub errors: Vec<BuildError>,
+    pub warnings: Vec<String>,
+    pub clients: Vec<ClientInfo>,
+}
+
+impl Status {
+    pub fn new(server: &Server) -> Self {
+        Self {
+            build: server.build_info(),
+            addresses: server
+                .addresses()
+                .iter()
+                .map(|address| address.to_string())
+                .collect(),
+            errors: server.errors(),
+            warnings: server.warnings(),
+            clients: server.clients(),
+        }
+    }
+}
+
+/// Rendered page reported by `/__wahoo/api/pages`
+#[derive(Debug, Serialize)]
+pub struct PageInfo {
+    pub url: String,
+    /// templates and files the page has been rendered from,
+    /// relative to the project folder
+    pub sources: Vec<String>,
+}
+
+impl PageInfo {
+    pub fn list(server: &Server) -> Vec<PageInfo> {
+        server
+            .pages()
+            .into_iter()
+            .map(|(url, sources)| PageInfo {
+                url,
+                sources: sources
+                    .iter()
+                    .map(|source| {
+                        source
+                            .strip_prefix(server.project_folder())
+                            .unwrap_or(source)
+                            .display()
+                            .to_string()
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+/// Register the dev dashboard at `/__wahoo/` along with its JSON endpoints
+pub fn register(server: Arc<Server>, app: &mut tide::Server<()>) {
+    for path in ["/__wahoo", "/__wahoo/"] {
+        app.at(path).get(|_| async {
+            Ok(Response::builder(StatusCode::Ok)
+                .body(DASHBOARD_HTML)
+                .content_type(mime::HTML)
+                .build())
+        });
+    }
+
+    let this = server.clone();
+    app.at("/__wahoo/api/status").get(move |_| {
+        let this = this.clone();
+        async move { Body::from_json(&Status::new(&this)) }
+    });
+
+    let this = server.clone();
+    app.at("/__wahoo/api/pages").get(move |_| {
+        let this = this.clone();
+        async move { Body::from_json(&PageInfo::list(&this)) }
+    });
+
+    /// `/__wahoo/api/navigate` request body
+    #[derive(Deserialize)]
+    struct Navigate {
+        url: String,
+    }
+
+    let this = server.clone();
+    app.at("/__wahoo/api/navigate")
+        .post(move |mut request: tide::Request<()>| {
+            let this = this.clone();
+            async move {
+                let Navigate { url } = request.body_json().await?;
+                let clients = this.navigate(&url, None).await.map_err(|err| {
+                    tide::Error::from_str(StatusCode::BadRequest, err.to_string())
+                })?;
+                Body::from_json(&clients)
+            }
+        });
+
+    app.at("/__wahoo/api/rebuild").post(move |_| {
+        let this = server.clone();
+        async move {
+            if this.rebuild() {
+                log_info!("Dashboard", "rebuild requested");
+                Ok(Response::new(StatusCode::Accepted))
+            } else {
+                Ok(Response::new(StatusCode::ServiceUnavailable))
+            }
+        }
+    });
+}
@@ -0,0 +1,144 @@
+use crate::prelude::*;
+use serde_json::{Map, Value};
+
+/// Folder (relative to the project folder) whose files are loaded as `data.<name>`
+pub const DATA_FOLDER: &str = "data";
+
+/// Format of a data file, detected from its extension
+/// or given by the `format` argument of `load_data()`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataFormat {
+    Json,
+    Yaml,
+    Csv,
+    Toml,
+}
+
+impl DataFormat {
+    pub fn from_path(path: &Path) -> Option<DataFormat> {
+        Self::from_name(path.extension()?.to_str()?).ok()
+    }
+
+    pub fn from_name(name: &str) -> Result<DataFormat> {
+        match name.to_lowercase().as_str() {
+            "json" => Ok(DataFormat::Json),
+            "yaml" | "yml" => Ok(DataFormat::Yaml),
+            "csv" => Ok(DataFormat::Csv),
+            "toml" => Ok(DataFormat::Toml),
+            _ => Err(format!(
+                "Unsupported data format `{name}` (expected json, yaml, csv or toml)"
+            )
+            .into()),
+        }
+    }
+}
+
+/// Load a data file; CSV files are loaded as an array
+/// of objects keyed by the column names of the header row
+pub fn load_data(path: &Path, format: DataFormat) -> Result<Value> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|err| format!("Unable to load `{}`: {err}", path.display()))?;
+    let value = match format {
+        DataFormat::Json => serde_json::from_str(&text).map_err(Error::from),
+        DataFormat::Yaml => serde_yaml::from_str(&text).map_err(Error::from),
+        DataFormat::Toml => toml::from_str::<toml::Value>(&text)
+            .map_err(Error::from)
+            .and_then(|toml| Ok(serde_json::to_value(toml)?)),
+        DataFormat::Csv => load_csv(&text),
+    };
+    value.map_err(|err| format!("Error loading `{}`: {err}", path.display()).into())
+}
+
+fn load_csv(text: &str) -> Result<Value> {
+    let mut reader = csv::Reader::from_reader(text.as_bytes());
+    let headers = reader.headers()?.clone();
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = record?;
+        let row = headers
+            .iter()
+            .zip(record.iter())
+            .map(|(header, value)| (header.to_string(), Value::String(value.to_string())))
+            .collect::<Map<_, _>>();
+        rows.push(Value::Object(row));
+    }
+    Ok(Value::Array(rows))
+}
+
+/// Data files available to templates as `data.<name>`: the files of the
+/// `data/` folder (subfolders become nested objects) followed by the
+/// `settings.data` declarations, which take precedence over folder entries
+pub fn data_files(ctx: &Context) -> Vec<(Vec<String>, PathBuf)> {
+    let mut files = vec![];
+    let folder = ctx.project_folder.join(DATA_FOLDER);
+    if folder.is_dir() {
+        for entry in walkdir::WalkDir::new(&folder)
+            .sort_by_file_name()
+            .into_iter()
+            .flatten()
+        {
+            let path = entry.path();
+            let relative = path.strip_prefix(&folder).unwrap();
+            if !path.is_file() || is_hidden(relative) || DataFormat::from_path(path).is_none() {
+                continue;
+            }
+            let name = relative
+                .with_extension("")
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy().to_string())
+                .collect();
+            files.push((name, path.to_path_buf()));
+        }
+    }
+
+    if let Some(data) = &ctx.manifest.data {
+        for (name, file) in data {
+            files.push((vec![name.clone()], ctx.project_folder.join(file)));
+        }
+    }
+
+    files
+}
+
+/// Load all data files into the object inserted as `data` in the template context
+pub fn load_data_files(ctx: &Context) -> Result<Value> {
+    let mut data = Map::new();
+    for (name, path) in data_files(ctx) {
+        let format = DataFormat::from_path(&path).ok_or_else(|| {
+            format!(
+                "Unable to detect the format of data file `{}`",
+                path.display()
+            )
+        })?;
+        let value = load_data(&path, format)?;
+
+        let (key, parents) = name.split_last().unwrap();
+        let mut target = &mut data;
+        for parent in parents {
+            let entry = target
+                .entry(parent.clone())
+                .or_insert_with(|| Value::Object(Map::new()));
+            if !entry.is_object() {
+                *entry = Value::Object(Map::new());
+            }
+            target = entry.as_object_mut().unwrap();
+        }
+        target.insert(key.clone(), value);
+    }
+    Ok(Value::Object(data))
+}
+
+/// Merge the loaded data files into the `data` value of the manifest,
+/// keeping the manifest values over the data file entries
+pub fn merge_data(target: &mut Value, data: Value) {
+    if let (Value::Object(target), Value::Object(data)) = (target, data) {
+        for (key, value) in data {
+            match target.get_mut(&key) {
+                Some(existing) => merge_data(existing, value),
+                None => {
+                    target.insert(key, value);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,340 @@
+use crate::prelude::*;
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+static TAGS: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"\{%-?\s*(?:include|extends|import)\b(.*?)-?%\}"#).unwrap());
+static LITERALS: Lazy<Regex> = Lazy::new(|| Regex::new(r#""([^"]+)"|'([^']+)'"#).unwrap());
+static FILTERS: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"["']([^"']+)["']\s*\|\s*include_file\b"#).unwrap());
+static FUNCTIONS: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r#"\b(?:include_file|markdown|read_md_file|read_md_files|render_file)\s*\(([^)]*)\)"#,
+    )
+    .unwrap()
+});
+static BLOCKS: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?s)\{\{.*?\}\}|\{%.*?%\}").unwrap());
+static COLLECTION: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\b(?:pages|get_page|get_section|breadcrumbs|section\.)").unwrap());
+static ARGS: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"\b(?:file|dir)\s*=\s*["']([^"']+)["']"#).unwrap());
+
+/// Relationships between source files and rendered pages, used
+/// to determine which pages are affected by a file change.
+#[derive(Default, Debug, Clone)]
+pub struct Dependencies {
+    /// template file => files referenced by the template
+    /// (include, extends, import, `include_file` etc.)
+    templates: AHashMap<PathBuf, AHashSet<PathBuf>>,
+    /// page url => files the page has been rendered from
+    pages: AHashMap<String, AHashSet<PathBuf>>,
+    /// templates reading the page index (`pages`, `get_page()` etc.)
+    collection: AHashSet<PathBuf>,
+    /// files the page index has been collected from
+    collected: AHashSet<PathBuf>,
+}
+
+impl Dependencies {
+    pub fn clear(&mut self) {
+        self.templates.clear();
+        self.pages.clear();
+        self.collection.clear();
+        self.collected.clear();
+    }
+
+    /// Scan template sources for static references to other templates
+    pub fn scan<'a, I>(&mut self, ctx: &Context, templates: I)
+    where
+        I: Iterator<Item = &'a str>,
+    {
+        for template in templates {
+            let path = ctx.template_file(template);
+            let text = match std::fs::read_to_string(&path) {
+                Ok(text) => text,
+                Err(_) => continue,
+            };
+
+            let references = template_references(&text)
+                .iter()
+                .map(|name| normalize_path(&ctx.template_file(name)))
+                .collect();
+            let path = normalize_path(&path);
+            if uses_collection(&text) {
+                self.collection.insert(path.clone());
+            }
+            self.templates.insert(path, references);
+        }
+    }
+
+    /// Register the files the page index has been collected from; templates
+    /// reading the index depend on all of them (titles, front matter)
+    pub fn collected(&mut self, sources: &[PathBuf]) {
+        self.collected
+            .extend(sources.iter().map(|path| normalize_path(path)));
+    }
+
+    /// Register a rendered page along with the files it was rendered from
+    pub fn page(&mut self, url: &str, sources: &[PathBuf]) {
+        self.pages
+            .entry(url.to_string())
+            .or_default()
+            .extend(sources.iter().map(|path| normalize_path(path)));
+    }
+
+    /// Rendered pages along with the files they were rendered from
+    pub fn pages(&self) -> Vec<(String, Vec<PathBuf>)> {
+        let mut pages = self
+            .pages
+            .iter()
+            .map(|(url, sources)| {
+                let mut sources = sources.iter().cloned().collect::<Vec<_>>();
+                sources.sort();
+                (url.clone(), sources)
+            })
+            .collect::<Vec<_>>();
+        pages.sort();
+        pages
+    }
+
+    pub fn urls(&self) -> Vec<String> {
+        let mut urls: Vec<_> = self.pages.keys().cloned().collect();
+        urls.sort();
+        urls
+    }
+
+    /// Returns urls of all pages affected by changes to the given files.
+    /// Files that are not known to any page or template (manifest,
+    /// TOML data, migrated assets) affect all pages.
+    pub fn affected(&self, changed: &[PathBuf]) -> Vec<String> {
+        let mut affected: AHashSet<PathBuf> = AHashSet::new();
+        for path in changed.iter().map(|path| normalize_path(path)) {
+            if !self.is_known(&path) {
+                return self.urls();
+            }
+            affected.insert(path);
+        }
+
+        if affected.iter().any(|path| self.collected.contains(path)) {
+            affected.extend(self.collection.iter().cloned());
+        }
+
+        // propagate changes to templates referencing the affected files
+        loop {
+            let dependents = self
+                .templates
+                .iter()
+                .filter(|(template, references)| {
+                    !affected.contains(*template) && Self::intersects(references, &affected)
+                })
+                .map(|(template, _)| template.clone())
+                .collect::<Vec<_>>();
+
+            if dependents.is_empty() {
+                break;
+            }
+            affected.extend(dependents);
+        }
+
+        let mut urls = self
+            .pages
+            .iter()
+            .filter(|(_, sources)| Self::intersects(sources, &affected))
+            .map(|(url, _)| url.clone())
+            .collect::<Vec<_>>();
+        urls.sort();
+        urls
+    }
+
+    fn is_known(&self, path: &Path) -> bool {
+        self.templates.contains_key(path)
+            || self
+                .templates
+                .values()
+                .any(|refs| Self::references(refs, path))
+            || self.pages.values().any(|refs| Self::references(refs, path))
+    }
+
+    /// A reference matches a file directly or by being one of its parent folders
+    fn references(references: &AHashSet<PathBuf>, path: &Path) -> bool {
+        references
+            .iter()
+            .any(|reference| path.starts_with(reference))
+    }
+
+    fn intersects(references: &AHashSet<PathBuf>, paths: &AHashSet<PathBuf>) -> bool {
+        paths.iter().any(|path| Self::references(references, path))
+    }
+}
+
+/// Whether a template source reads the page index within its tags
+fn uses_collection(text: &str) -> bool {
+    BLOCKS
+        .find_iter(text)
+        .any(|block| COLLECTION.is_match(block.as_str()))
+}
+
+/// Names of the templates and files statically referenced by a template
+/// source, relative to the templates folder
+fn template_references(text: &str) -> Vec<&str> {
+    let mut references = vec![];
+    for captures in TAGS.captures_iter(text) {
+        for literal in LITERALS.captures_iter(captures.get(1).unwrap().as_str()) {
+            if let Some(name) = literal.get(1).or_else(|| literal.get(2)) {
+                references.push(name.as_str());
+            }
+        }
+    }
+    for captures in FILTERS.captures_iter(text) {
+        references.push(captures.get(1).unwrap().as_str());
+    }
+    for captures in FUNCTIONS.captures_iter(text) {
+        for arg in ARGS.captures_iter(captures.get(1).unwrap().as_str()) {
+            references.push(arg.get(1).unwrap().as_str());
+        }
+    }
+    references
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(paths: &[&str]) -> AHashSet<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    /// `index.html` extends `base.html`, which includes `nav.html`;
+    /// `docs.html` reads the markdown files of the `docs` folder
+    fn dependencies() -> Dependencies {
+        let mut dependencies = Dependencies::default();
+        dependencies
+            .templates
+            .insert("/t/index.html".into(), set(&["/t/base.html"]));
+        dependencies
+            .templates
+            .insert("/t/base.html".into(), set(&["/t/nav.html"]));
+        dependencies
+            .templates
+            .insert("/t/docs.html".into(), set(&["/t/docs"]));
+        dependencies.page("/", &["/t/index.html".into()]);
+        dependencies.page("/about", &["/t/index.html".into(), "/s/about.md".into()]);
+        dependencies.page("/docs", &["/t/docs.html".into()]);
+        dependencies
+    }
+
+    #[test]
+    fn extracts_tag_references() {
+        let text = r#"
+            {% extends "base.html" %}
+            {%- include 'partials/nav.html' -%}
+            {% include ["a.html", "b.html"] ignore missing %}
+            {% import "macros.html" as macros %}
+            {% include dynamic_name %}
+        "#;
+        assert_eq!(
+            template_references(text),
+            [
+                "base.html",
+                "partials/nav.html",
+                "a.html",
+                "b.html",
+                "macros.html"
+            ]
+        );
+    }
+
+    #[test]
+    fn extracts_file_references() {
+        let text = r#"
+            {{ "footer.md" | include_file }}
+            {{ include_file(file="header.html") }}
+            {{ markdown(file = 'intro.md', inline=true) }}
+            {% for doc in read_md_files(dir="docs") %}{% endfor %}
+            {{ render_file(context=page) }}
+        "#;
+        assert_eq!(
+            template_references(text),
+            ["footer.md", "header.html", "intro.md", "docs"]
+        );
+    }
+
+    #[test]
+    fn ignores_plain_text() {
+        let text = r#"<p>"include" and 'extends' are keywords, include_file too</p>"#;
+        assert!(template_references(text).is_empty());
+    }
+
+    #[test]
+    fn propagates_changes_through_templates() {
+        let dependencies = dependencies();
+        assert_eq!(
+            dependencies.affected(&["/t/nav.html".into()]),
+            ["/", "/about"]
+        );
+        assert_eq!(
+            dependencies.affected(&["/t/index.html".into()]),
+            ["/", "/about"]
+        );
+        assert_eq!(dependencies.affected(&["/s/about.md".into()]), ["/about"]);
+    }
+
+    #[test]
+    fn folder_references_match_their_files() {
+        let dependencies = dependencies();
+        assert_eq!(
+            dependencies.affected(&["/t/docs/guide/setup.md".into()]),
+            ["/docs"]
+        );
+    }
+
+    #[test]
+    fn detects_page_index_usage() {
+        assert!(uses_collection("{% for page in pages %}{% endfor %}"));
+        assert!(uses_collection(
+            r#"{{ get_page(path="about.html").title }}"#
+        ));
+        assert!(uses_collection(
+            r#"{% set blog = get_section(name="blog") %}"#
+        ));
+        assert!(uses_collection("{{ section.title }}"));
+        assert!(uses_collection(
+            "{% for crumb in breadcrumbs %}{% endfor %}"
+        ));
+        assert!(!uses_collection("<section>pages</section>{{ page.title }}"));
+    }
+
+    #[test]
+    fn page_index_changes_affect_its_readers() {
+        let mut dependencies = dependencies();
+        // `menu.html`, included by `base.html`, lists the pages
+        dependencies
+            .templates
+            .insert("/t/menu.html".into(), set(&[]));
+        dependencies
+            .templates
+            .get_mut(Path::new("/t/base.html"))
+            .unwrap()
+            .insert("/t/menu.html".into());
+        dependencies.collection.insert("/t/menu.html".into());
+        dependencies.collected(&["/t/index.html".into(), "/s/about.md".into()]);
+
+        assert_eq!(
+            dependencies.affected(&["/s/about.md".into()]),
+            ["/", "/about"]
+        );
+        // files outside the index do not affect its readers
+        assert_eq!(
+            dependencies.affected(&["/t/docs/guide/setup.md".into()]),
+            ["/docs"]
+        );
+    }
+
+    #[test]
+    fn unknown_files_affect_all_pages() {
+        let dependencies = dependencies();
+        assert_eq!(
+            dependencies.affected(&["/t/nav.html".into(), "/wahoo.toml".into()]),
+            ["/", "/about", "/docs"]
+        );
+    }
+}
@@ -1,8 +1,17 @@
 use globset::Error as GlobError;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Serialize;
+use std::error::Error as _;
 use std::ffi::OsString;
+use std::path::Path;
 use thiserror::Error;
 use tide::Error as TideError;
 
+static TERA_TEMPLATE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"Failed to (?:parse|render) ['"](.+?)['"]"#).unwrap());
+static TERA_LOCATION: Lazy<Regex> = Lazy::new(|| Regex::new(r"-->\s*(\d+):(\d+)").unwrap());
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("Error: {0}")]
@@ -38,6 +47,12 @@ pub enum Error {
     #[error("Toml Deserialize: {0}")]
     TomlDeserialize(#[from] toml::de::Error),
 
+    #[error("YAML error: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+
+    #[error("CSV error: {0}")]
+    Csv(#[from] csv::Error),
+
     #[error(transparent)]
     Notify(#[from] notify::Error),
 }
@@ -65,3 +80,74 @@ impl From<OsString> for Error {
         Error::OsString(format!("{os_str:?}"))
     }
 }
+
+/// Template error reported to the browser during `serve`
+#[derive(Debug, Clone, Serialize)]
+pub struct BuildError {
+    pub template: Option<String>,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+    pub snippet: Option<String>,
+}
+
+impl BuildError {
+    pub fn new(message: &str) -> Self {
+        Self {
+            template: None,
+            line: None,
+            column: None,
+            message: message.to_string(),
+            snippet: None,
+        }
+    }
+
+    /// Extract the template name, location and source snippet from a tera error chain
+    pub fn from_tera(err: &tera::Error, templates_folder: &Path) -> Self {
+        let mut messages = vec![err.to_string()];
+        let mut cause = err.source();
+        while let Some(err) = cause {
+            messages.push(err.to_string());
+            cause = err.source();
+        }
+        let message = messages.join("\n");
+
+        let template = TERA_TEMPLATE.captures(&message).map(|captures| {
+            let name = Path::new(&captures[1]);
+            name.strip_prefix(templates_folder)
+                .unwrap_or(name)
+                .to_string_lossy()
+                .to_string()
+        });
+
+        let location = TERA_LOCATION
+            .captures(&message)
+            .map(|captures| (captures[1].parse().ok(), captures[2].parse().ok()));
+        let (line, column): (Option<usize>, Option<usize>) = location.unwrap_or((None, None));
+
+        let snippet = match (&template, line) {
+            (Some(template), Some(line)) => {
+                std::fs::read_to_string(templates_folder.join(template))
+                    .ok()
+                    .map(|text| {
+                        text.lines()
+                            .enumerate()
+                            .skip(line.saturating_sub(3))
+                            .take(5)
+                            .map(|(index, text)| format!("{:>4} | {text}", index + 1))
+                            .collect::<Vec<_>>()
+                            .join("\n")
+                    })
+            }
+            _ => None,
+        };
+
+        Self {
+            template,
+            line,
+            column,
+            message,
+            snippet,
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use crate::prelude::*;
+use once_cell::sync::OnceCell;
 use serde_json::Value;
 use std::collections::HashMap;
 use tera::Context;
@@ -169,7 +170,12 @@ fn read_md_file_impl(
     Ok(value)
 }
 
-pub fn read_md_file(template_folder: &Path, args: &HashMap<String, Value>) -> tera::Result<Value> {
+/// `root_folder` is the project folder, paths are reported relative to it
+pub fn read_md_file(
+    template_folder: &Path,
+    root_folder: &Path,
+    args: &HashMap<String, Value>,
+) -> tera::Result<Value> {
     let file_path = if let Some(file) = args.get("file") {
         if let Some(file) = file.as_str() {
             file
@@ -194,17 +200,16 @@ pub fn read_md_file(template_folder: &Path, args: &HashMap<String, Value>) -> te
         }
     }
 
-    let root_folder = template_folder
-        .parent()
-        .unwrap()
-        .parent()
-        .unwrap()
-        .to_str()
-        .unwrap();
+    let root_folder = root_folder.to_str().unwrap();
 
     read_md_file_impl(&path, root_folder, open_in_new_window)
 }
-pub fn read_md_files(template_folder: &Path, args: &HashMap<String, Value>) -> tera::Result<Value> {
+/// `root_folder` is the project folder, paths are reported relative to it
+pub fn read_md_files(
+    template_folder: &Path,
+    root_folder: &Path,
+    args: &HashMap<String, Value>,
+) -> tera::Result<Value> {
     let dir_path = if let Some(file) = args.get("dir") {
         if let Some(file) = file.as_str() {
             file
@@ -243,13 +248,7 @@ pub fn read_md_files(template_folder: &Path, args: &HashMap<String, Value>) -> t
         }
     }
     let mut md_list = Vec::new();
-    let root_folder = template_folder
-        .parent()
-        .unwrap()
-        .parent()
-        .unwrap()
-        .to_str()
-        .unwrap();
+    let root_folder = root_folder.to_str().unwrap();
     for path in list {
         md_list.push(read_md_file_impl(&path, root_folder, open_in_new_window)?);
     }
@@ -259,20 +258,39 @@ pub fn read_md_files(template_folder: &Path, args: &HashMap<String, Value>) -> t
 
 #[derive(Clone)]
 pub struct IncludeFile {
-    pub project_folder: PathBuf,
+    pub templates_folder: PathBuf,
+    /// theme and workspace template folders, overridden by `templates_folder`
+    pub shared_templates: Vec<PathBuf>,
     pub dir: String,
     pub context: tera::Context,
+    /// templates parsed on first use and shared by the includes of a render pass
+    merged: Arc<OnceCell<tera::Tera>>,
 }
 
 impl IncludeFile {
-    pub fn new(project_folder: PathBuf, dir: &str, context: tera::Context) -> Self {
+    pub fn new(
+        templates_folder: PathBuf,
+        shared_templates: Vec<PathBuf>,
+        dir: &str,
+        context: tera::Context,
+    ) -> Self {
         Self {
-            project_folder,
+            templates_folder,
+            shared_templates,
             dir: dir.to_string(),
             context,
+            merged: Arc::new(OnceCell::new()),
         }
     }
 
+    /// Tera instance holding the project and shared templates
+    fn tera(&self) -> tera::Result<tera::Tera> {
+        let mut tera = self.merged.get_or_try_init(|| self.create_tera())?.clone();
+        // registered on the copy, the cached instance must not own the filter
+        tera.register_filter("include_file", self.clone());
+        Ok(tera)
+    }
+
     fn create_tera(&self) -> tera::Result<tera::Tera> {
         let mut tera = match tera::Tera::new(&self.dir) {
             Ok(t) => t,
@@ -281,22 +299,29 @@ impl IncludeFile {
                 return Err(e);
             }
         };
+        let glob = Path::new(&self.dir)
+            .strip_prefix(&self.templates_folder)
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        for folder in self.shared_templates.iter() {
+            let dir = folder.join(&glob);
+            let shared = tera::Tera::new(dir.to_str().unwrap())?;
+            tera.extend(&shared)?;
+        }
 
         let log = Log {};
         let sort_object = SortObject {};
         let markdown_filter = Markdown {};
-        let include_file = self.clone();
 
-        let project_folder = self.project_folder.join("templates");
+        let templates_folder = self.templates_folder.clone();
 
         tera.register_filter("sort_object", sort_object);
         tera.register_filter("markdown", markdown_filter);
-        tera.register_filter("include_file", include_file);
         tera.register_filter("log", log);
         tera.register_function(
             "markdown",
             move |args: &HashMap<String, Value>| -> tera::Result<Value> {
-                let value = markdown(&project_folder, args)?;
+                let value = markdown(&templates_folder, args)?;
                 Ok(value)
             },
         );
@@ -317,7 +342,7 @@ impl tera::Filter for IncludeFile {
         }
         let mut template = value.as_str().unwrap();
 
-        let mut tera = self.create_tera()?;
+        let mut tera = self.tera()?;
 
         let templates: Vec<&str> = tera.get_template_names().collect();
         let mut rendering_fallback = false;
@@ -331,7 +356,12 @@ impl tera::Filter for IncludeFile {
         }
 
         if !templates.contains(&template) {
-            let path = self.project_folder.join("templates").join(template);
+            let path = [&self.templates_folder]
+                .into_iter()
+                .chain(self.shared_templates.iter())
+                .map(|folder| folder.join(template))
+                .find(|path| path.exists())
+                .unwrap_or_else(|| self.templates_folder.join(template));
             if path.exists() {
                 let path = path.canonicalize()?;
                 //println!("path: {:?}", path);
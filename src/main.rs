@@ -1,21 +1,34 @@
 use clap::{Parser, Subcommand};
 use console::style;
 
+pub mod asset;
 pub mod builder;
+pub mod collection;
 pub mod context;
+pub mod dashboard;
+pub mod data;
+pub mod dependencies;
 pub mod error;
 pub mod filter;
 pub mod log;
 pub mod manifest;
 pub mod markdown;
 pub mod prelude;
+pub mod proxy;
 pub mod result;
+pub mod rules;
+pub mod schema;
 pub mod server;
 pub mod sink;
+pub mod theme;
+pub mod tls;
 pub mod utils;
+pub mod workspace;
 
 use prelude::*;
-use server::Server;
+use schema::FileKind;
+use server::{ServedSite, Server};
+use tls::Tls;
 
 // #[derive(Debug, Parser)]
 // #[clap(name = "cargo")]
@@ -40,7 +53,8 @@ use server::Server;
     // setting = clap::AppSettings::DeriveDisplayOrder,
 )]
 struct Args {
-    /// Location of the nw.toml manifest file
+    /// Location of the wahoo.toml manifest file (default: `WAHOO_MANIFEST`
+    /// or the nearest `wahoo.toml` in the current folder or its parents)
     #[clap(name = "manifest")]
     location: Option<String>,
     /// Action to execute (build,clean,init)
@@ -49,6 +63,16 @@ struct Args {
     /// Enable verbose mode
     #[clap(short, long)]
     verbose: bool,
+    /// Override a manifest value, i.e. `-D site.base_url=https://example.com`
+    #[clap(short = 'D', long = "define", value_name = "KEY=VALUE", global = true)]
+    defines: Vec<String>,
+    /// Build environment selecting the `[env.<name>]` manifest overlay
+    /// (default: `development` for `serve`, `production` otherwise)
+    #[clap(long, global = true)]
+    env: Option<String>,
+    /// Output folder overriding `settings.output`
+    #[clap(long, global = true)]
+    output: Option<PathBuf>,
 }
 
 #[derive(Subcommand, Debug)]
@@ -57,16 +81,38 @@ enum Action {
     Build {},
     /// Serve the site via HTTP; Monitor and re-render if changed
     Serve {
-        /// HTTP server host
-        #[clap(long, default_value = "127.0.0.1")]
-        host: String,
+        /// HTTP server host, repeat to listen on multiple hosts
+        /// (default: `serve.host` or 127.0.0.1)
+        #[clap(long)]
+        host: Vec<String>,
 
-        /// HTTP port to listen on
-        #[clap(long, default_value = "8080")]
-        port: u16,
+        /// HTTP port to listen on, the next free port is used if it is taken
+        /// and `0` picks any free port (default: `serve.port` or 8080)
+        #[clap(long)]
+        port: Option<u16>,
+
+        /// Serve over HTTPS using a cached self-signed certificate
+        #[clap(long)]
+        https: bool,
+
+        /// PEM certificate to serve HTTPS with (implies `--https`)
+        #[clap(long, requires = "key")]
+        cert: Option<PathBuf>,
+
+        /// PEM private key of the `--cert` certificate
+        #[clap(long, requires = "cert")]
+        key: Option<PathBuf>,
     },
     /// Delete the rendered site files
     Clean {},
+    /// Print the manifest with all imports merged
+    Config {},
+    /// Print the JSON schema of `wahoo.toml` for editor autocompletion
+    Schema {
+        /// Print the schema of section files instead
+        #[clap(long)]
+        section: bool,
+    },
     /// Create a basic site template (TODO)
     Init {},
     /// Publish the site (TODO)
@@ -78,7 +124,18 @@ pub async fn async_main() -> Result<()> {
         location,
         action,
         verbose,
+        defines,
+        env,
+        output,
     } = Args::parse();
+    let defines = get_env_defs(&defines)?;
+    let env = env.unwrap_or_else(|| {
+        if matches!(action, Action::Serve { .. }) {
+            "development".to_string()
+        } else {
+            "production".to_string()
+        }
+    });
 
     if verbose {
         log::enable_verbose();
@@ -86,90 +143,107 @@ pub async fn async_main() -> Result<()> {
 
     match action {
         Action::Build {} => {
-            let ctx = Arc::new(Context::create(location, Options::default()).await?);
-            let build = Arc::new(Builder::new(ctx.clone()));
-            build.execute().await?;
-            println!();
+            let options = Options {
+                env,
+                defines,
+                output,
+                ..Options::default()
+            };
+            let project = Project::load(location, &options).await?;
+            for site in project.sites.iter() {
+                let build = Arc::new(Builder::new(site.ctx.clone()));
+                build.execute().await?;
+                println!();
+            }
 
             // ~~~
             // println!("{:#?}", ctx.manifest);
         }
+        Action::Config {} => {
+            let options = Options {
+                env,
+                defines,
+                ..Options::default()
+            };
+            let manifest = Manifest::load(&Manifest::locate(location).await?, &options).await?;
+            let toml = toml::to_string_pretty(&manifest.toml).map_err(|err| err.to_string())?;
+            println!("{toml}");
+        }
+        Action::Schema { section } => {
+            let kind = if section {
+                FileKind::Section
+            } else {
+                FileKind::Manifest
+            };
+            println!("{}", serde_json::to_string_pretty(&kind.schema())?);
+        }
         Action::Clean {} => {
-            let ctx = Arc::new(Context::create(location, Options::default()).await?);
-
-            ctx.clean().await?;
+            let options = Options {
+                env,
+                defines,
+                output,
+                ..Options::default()
+            };
+            let project = Project::load(location, &options).await?;
+            for site in project.sites.iter() {
+                site.ctx.clean().await?;
+            }
         }
-        Action::Serve { host, port } => {
-            let sink = Sink::default();
-
-            let ctx = {
-                let ctx = Arc::new(
-                    Context::create(
-                        location.clone(),
-                        Options {
-                            server: true,
-                            verbose: true,
-                        },
-                    )
-                    .await?,
-                );
-                let build = Arc::new(Builder::new_with_sink(ctx.clone(), sink.clone()));
-                build.execute().await?;
-                ctx
+        Action::Serve {
+            host,
+            port,
+            https,
+            cert,
+            key,
+        } => {
+            let options = Options {
+                server: true,
+                verbose: true,
+                env,
+                defines,
+                output,
+                member: None,
             };
+            let project = Project::load(location.clone(), &options).await?;
 
-            let mut watch_targets = ctx.manifest.imports.clone();
-            watch_targets.extend([ctx.manifest_toml.clone(), ctx.src_folder.clone()]);
-
-            if let Some(Settings {
-                watch: Some(watch), ..
-            }) = &ctx.manifest.settings
-            {
-                for folder in watch.iter() {
-                    let target_folder = ctx.project_folder.join(folder);
-                    let watch_target = target_folder.canonicalize().unwrap_or_else(|err| {
-                        format!(
-                            "Unable to locate watch target `{}`: {err}",
-                            target_folder.display()
-                        )
-                        .into()
-                    });
-                    watch_targets.push(watch_target);
-                }
-            }
-            // println!("{:#?}", ctx.manifest.sections);
-            if let Some(sections) = &ctx.manifest.sections {
-                for (_name, section) in sections.iter() {
-                    if let Some(SectionSettings {
-                        folder: Some(folder),
-                        ..
-                    }) = &section.settings
-                    {
-                        let target_folder = ctx.project_folder.join(folder);
-                        let watch_target = target_folder.canonicalize().unwrap_or_else(|err| {
-                            format!(
-                                "Unable to locate section folder `{}`: {err}",
-                                target_folder.display()
-                            )
-                            .into()
-                        });
-                        watch_targets.push(watch_target);
-                    }
-                }
+            // serve the sites from memory, leaving their `site/` folders untouched
+            let mut sites = vec![];
+            for site in project.sites.iter() {
+                let sink = Sink::memory();
+                let build = Arc::new(Builder::new_with_sink(site.ctx.clone(), sink.clone()));
+                build.execute().await?;
+                sites.push(ServedSite::new(site, sink));
             }
 
+            let watch_targets = project.watch_targets();
             log_trace!("Watching", "{watch_targets:#?}");
 
+            let serve = project.serve.clone().unwrap_or_default();
+            let hosts = if !host.is_empty() {
+                host
+            } else if let Some(hosts) = &serve.host {
+                hosts.to_vec()
+            } else {
+                vec!["127.0.0.1".to_string()]
+            };
+            let port = port.or(serve.port).unwrap_or(8080);
+            let tls = match (cert, key) {
+                (Some(cert), Some(key)) => Some(Tls::load(&cert, &key)?),
+                _ if https => Some(Tls::self_signed(&hosts)?),
+                _ => None,
+            };
+
             let server = Server::new(
-                host,
+                hosts,
                 port,
                 location,
-                ctx.project_folder.clone(),
-                ctx.site_folder.clone(),
+                options,
+                project.folder.clone(),
                 &watch_targets,
-                ctx.settings(),
-                sink,
-            );
+                serve,
+                sites,
+                tls,
+            )?;
 
             server.run().await?;
         }
@@ -1,21 +1,59 @@
 use crate::prelude::*;
+use crate::schema::{validate, FileKind};
 use async_std::fs::*;
+use futures::future::BoxFuture;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use schemars::JsonSchema;
 // use async_std::path::{Path, PathBuf};
 
+static ENV_REFERENCE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\$\$\{|\$\{([A-Za-z_][A-Za-z0-9_]*)(:-([^}]*))?\}").unwrap());
+
 #[derive(Debug, Clone)]
 pub struct Manifest {
     pub toml: toml::Value,
     pub settings: Option<Settings>,
+    pub serve: Option<ServeSettings>,
     pub sections: Option<HashMap<String, Section>>,
+    /// `settings.data` declarations: name => data file relative to the project folder
+    pub data: Option<HashMap<String, String>>,
     pub imports: Vec<PathBuf>,
+    /// active build environment (`--env`)
+    pub env: String,
+    /// unknown keys found in the manifest, its imports and section files
+    pub warnings: Vec<String>,
+    pub workspace: Option<WorkspaceSettings>,
+    /// `settings.theme` followed by the themes it extends
+    pub themes: Vec<Theme>,
 }
 
+/// Manifest file name searched for in the current folder and its parents
+pub const MANIFEST_FILE: &str = "wahoo.toml";
+/// Environment variable holding the manifest location
+pub const MANIFEST_ENV: &str = "WAHOO_MANIFEST";
+
+/// Build environments that may be selected without an `[env.<name>]` table
+pub const ENVIRONMENTS: &[&str] = &["production", "staging", "development"];
+
 impl Manifest {
+    /// Locate the manifest given as `location` or by the `WAHOO_MANIFEST`
+    /// environment variable (a file, a file without the `.toml` extension or
+    /// a folder containing `wahoo.toml`); otherwise search the current folder
+    /// and its parents for the nearest `wahoo.toml`
     pub async fn locate(location: Option<String>) -> Result<PathBuf> {
         let cwd = current_dir().await;
 
-        let location = if let Some(location) = location {
-            if let Some(stripped) = location.strip_prefix("~/") {
+        let (location, origin) = match location {
+            Some(location) => (Some(location), None),
+            None => match std::env::var(MANIFEST_ENV) {
+                Ok(location) if !location.is_empty() => (Some(location), Some(MANIFEST_ENV)),
+                _ => (None, None),
+            },
+        };
+
+        let locations = if let Some(location) = location {
+            let location = if let Some(stripped) = location.strip_prefix("~/") {
                 home::home_dir()
                     .expect("unable to get home directory")
                     .join(stripped)
@@ -26,29 +64,50 @@ impl Manifest {
                 } else {
                     cwd.join(&location)
                 }
-            }
-        } else {
-            cwd
-        };
+            };
 
-        let locations = [
-            &location,
-            &location.with_extension("toml"),
-            &location.join("wahoo.toml"),
-        ];
-
-        for location in locations.iter() {
-            if let Ok(location) = location.canonicalize() {
-                if location.is_file() {
-                    return Ok(location);
+            let locations = vec![
+                location.clone(),
+                location.with_extension("toml"),
+                location.join(MANIFEST_FILE),
+            ];
+            for location in locations.iter() {
+                if let Ok(location) = location.canonicalize() {
+                    if location.is_file() {
+                        return Ok(location);
+                    }
                 }
             }
-        }
+            locations
+        } else {
+            if let Some(location) = search_upwards(&cwd, MANIFEST_FILE).await {
+                return Ok(location.canonicalize()?);
+            }
+            cwd.ancestors()
+                .map(|folder| folder.join(MANIFEST_FILE))
+                .collect()
+        };
 
-        Err("Unable to locate 'wahoo.toml' manifest".into())
+        let origin = origin
+            .map(|variable| format!(" (from `{variable}`)"))
+            .unwrap_or_default();
+        let checked = locations
+            .iter()
+            .map(|location| format!("\n    {}", location.display()))
+            .collect::<String>();
+        Err(
+            format!("Unable to locate '{MANIFEST_FILE}' manifest{origin}, checked:{checked}")
+                .into(),
+        )
     }
 
-    pub async fn load_toml<F, P>(folder: F, toml_file: P) -> Result<toml::Value>
+    /// Load and validate a TOML file, appending unknown key warnings to `warnings`
+    pub async fn load_toml<F, P>(
+        folder: F,
+        toml_file: P,
+        kind: FileKind,
+        warnings: &mut Vec<String>,
+    ) -> Result<toml::Value>
     where
         F: AsRef<Path>,
         P: AsRef<Path>,
@@ -59,16 +118,80 @@ impl Manifest {
             format!("Unable to load `{}`: {err}", toml_file.display()).into()
         })?;
         let toml_text = read_to_string(&toml_file).await?;
-        let toml: toml::Value = match toml::from_str(&toml_text) {
+        let mut toml: toml::Value = match toml::from_str(&toml_text) {
             Ok(manifest) => manifest,
             Err(err) => {
                 return Err(format!("Error loading `{}`: {err}", toml_file.display()).into());
             }
         };
+        warnings.extend(validate(&toml_file, &toml_text, kind)?);
+        interpolate_env(&mut toml)
+            .map_err(|err| format!("Error loading `{}`: {err}", toml_file.display()))?;
         Ok(toml)
     }
 
-    pub async fn load(toml_file: &PathBuf) -> Result<Manifest> {
+    /// Load an imported TOML file along with the files it imports in turn;
+    /// `stack` holds the chain of importing files, used to detect cycles
+    fn load_import<'a>(
+        folder: &'a Path,
+        import: &'a str,
+        arrays: ArrayMerge,
+        stack: &'a mut Vec<PathBuf>,
+        imports: &'a mut Vec<PathBuf>,
+        warnings: &'a mut Vec<String>,
+    ) -> BoxFuture<'a, Result<toml::Value>> {
+        Box::pin(async move {
+            let mut toml = Self::load_toml(folder, import, FileKind::Manifest, warnings).await?;
+            let import_path = folder.join(import).canonicalize()?;
+            if stack.contains(&import_path) {
+                let chain = stack
+                    .iter()
+                    .chain([&import_path])
+                    .map(|path| format!("`{}`", path.display()))
+                    .collect::<Vec<_>>()
+                    .join(" -> ");
+                return Err(format!("Import cycle detected: {chain}").into());
+            }
+            if !imports.contains(&import_path) {
+                imports.push(import_path.clone());
+            }
+
+            // the nested import list is taken out of the imported file so that
+            // merging it does not replace the importing file's `settings.import`
+            let nested = toml
+                .get_mut("settings")
+                .and_then(|settings| settings.as_table_mut())
+                .and_then(|settings| settings.remove("import"))
+                .as_ref()
+                .and_then(|import| import.as_array())
+                .map(|list| {
+                    list.iter()
+                        .filter_map(|import| import.as_str().map(String::from))
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default();
+
+            // nested imports are relative to the importing file
+            let import_folder = import_path.parent().unwrap().to_path_buf();
+            stack.push(import_path);
+            for import in nested.iter() {
+                let toml_import =
+                    Self::load_import(&import_folder, import, arrays, stack, imports, warnings)
+                        .await?;
+                merge_toml(&mut toml, toml_import, arrays);
+            }
+            stack.pop();
+
+            Ok(toml)
+        })
+    }
+
+    /// Load the manifest along with its imports (preceded by the imports shared
+    /// by workspace members) and the `[env.<env>]` overlay; `-D` overrides
+    /// (`Options::defines`) are applied last
+    pub async fn load(toml_file: &PathBuf, options: &Options) -> Result<Manifest> {
+        let env = options.env.as_str();
+        let defines = &options.defines;
         let folder = toml_file.parent().unwrap();
         let toml_text = read_to_string(toml_file).await?;
         let mut toml: toml::Value = match toml::from_str(&toml_text) {
@@ -77,6 +200,13 @@ impl Manifest {
                 return Err(format!("Error loading wahoo.toml: {err}").into());
             }
         };
+        let mut warnings = validate(toml_file, &toml_text, FileKind::Manifest)?;
+        interpolate_env(&mut toml).map_err(|err| format!("Error loading wahoo.toml: {err}"))?;
+        for (path, value) in defines {
+            define_toml(&mut toml, path, value)?;
+        }
+        // settings are read from the manifest itself, not from the imports
+        let mut base = toml.clone();
         // println!("loading settings...");
         let settings = if let Some(settings) = toml.get("settings") {
             let settings: Settings = settings.clone().try_into()?;
@@ -86,21 +216,92 @@ impl Manifest {
         };
         // println!("loading sections...");
 
+        let arrays = settings
+            .as_ref()
+            .and_then(|settings| settings.merge_arrays)
+            .unwrap_or_default();
         let mut imports = vec![];
-        if let Some(settings) = &settings {
-            if let Some(import_list) = &settings.import {
-                for import in import_list.iter() {
-                    let toml_import = Self::load_toml(folder, import).await?;
-                    let import_path = folder.join(import).canonicalize().unwrap();
-                    imports.push(import_path);
-                    // println!("{:#?}", toml);
-                    let target = toml.as_table_mut().unwrap();
-                    for (k, v) in toml_import.as_table().unwrap().iter() {
-                        target.insert(k.clone(), v.clone());
-                    }
-                }
+        let mut import_list = options
+            .member
+            .iter()
+            .flat_map(|member| member.imports.iter())
+            .map(|import| import.to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        if let Some(Settings {
+            import: Some(import),
+            ..
+        }) = &settings
+        {
+            import_list.extend(import.iter().cloned());
+        }
+        if !import_list.is_empty() {
+            let mut stack = vec![toml_file.canonicalize()?];
+            for import in import_list.iter() {
+                let toml_import = Self::load_import(
+                    folder,
+                    import,
+                    arrays,
+                    &mut stack,
+                    &mut imports,
+                    &mut warnings,
+                )
+                .await?;
+                merge_toml(&mut toml, toml_import, arrays);
             }
         }
+
+        match toml.get("env").and_then(|envs| envs.get(env)).cloned() {
+            Some(overlay) => {
+                merge_toml(&mut base, overlay.clone(), arrays);
+                merge_toml(&mut toml, overlay, arrays);
+            }
+            None if !ENVIRONMENTS.contains(&env) => {
+                return Err(format!(
+                    "Unknown environment `{env}`: no `[env.{env}]` table in wahoo.toml"
+                )
+                .into());
+            }
+            None => {}
+        }
+
+        // overrides take precedence over imported and environment values
+        for (path, value) in defines {
+            define_toml(&mut toml, path, value)?;
+            define_toml(&mut base, path, value)?;
+        }
+
+        let settings = if let Some(settings) = base.get("settings") {
+            let settings: Settings = settings.clone().try_into()?;
+            Some(settings)
+        } else {
+            None
+        };
+
+        // theme data provides defaults for the manifest and its imports
+        let themes = match settings
+            .as_ref()
+            .and_then(|settings| settings.theme.as_ref())
+        {
+            Some(theme) => Theme::load_chain(folder, theme, &mut warnings).await?,
+            None => vec![],
+        };
+        if !themes.is_empty() {
+            let mut defaults = toml::Value::Table(toml::Table::new());
+            for theme in themes.iter().rev() {
+                merge_toml(&mut defaults, theme.toml.clone(), arrays);
+            }
+            merge_toml(&mut defaults, toml, arrays);
+            toml = defaults;
+        }
+
+        let serve = if let Some(serve) = base.get("serve") {
+            let serve: ServeSettings = serve.clone().try_into()?;
+            // proxy targets are checked when the manifest is loaded
+            crate::proxy::Proxy::from_settings(&serve)?;
+            Some(serve)
+        } else {
+            None
+        };
         // panic!();
         let sections = if let Some(Settings {
             sections: Some(sections_list),
@@ -117,7 +318,8 @@ impl Manifest {
             for section in sections_list.iter() {
                 log_trace!("Section", "loading {section}");
                 // if let Some(index) = &section_ref.index {
-                let section_toml = Self::load_toml(folder, &section).await?;
+                let section_toml =
+                    Self::load_toml(folder, &section, FileKind::Section, &mut warnings).await?;
                 let section_path = folder.join(section).canonicalize().unwrap();
                 imports.push(section_path);
                 let settings = if let Some(settings) = section_toml.get("settings") {
@@ -149,6 +351,18 @@ impl Manifest {
             None
         };
 
+        let workspace = if let Some(workspace) = base.get("workspace") {
+            let workspace: WorkspaceSettings = workspace.clone().try_into()?;
+            Some(workspace)
+        } else {
+            None
+        };
+        let data = settings.as_ref().and_then(|settings| settings.data.clone());
+
+        for warning in warnings.iter() {
+            log_warn!("Manifest", "{warning}");
+        }
+
         //let table = toml.as_table().unwrap();
         //println!("{:#?}", table);
         //println!("settings: {:#?}", settings);
@@ -156,14 +370,30 @@ impl Manifest {
         Ok(Manifest {
             toml,
             settings,
+            serve,
             sections,
+            data,
             imports,
+            env: env.to_string(),
+            warnings,
+            workspace,
+            themes,
         })
     }
 }
 
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct Settings {
+    /// source folder, relative to the project folder (default: `src`)
+    pub source: Option<String>,
+    /// templates folder, relative to the source folder (default: `templates`)
+    pub templates: Option<String>,
+    /// output folder, relative to the project folder (default: `site`)
+    pub output: Option<String>,
+    /// theme folder, relative to the project folder, providing `templates/`,
+    /// `static/` assets and default data (`theme.toml`)
+    pub theme: Option<String>,
     pub ignore: Option<Vec<String>>,
     pub languages: Option<Vec<String>>,
     pub map: Option<Vec<DataMap>>,
@@ -175,16 +405,189 @@ pub struct Settings {
     pub markdown: Option<String>,
     #[serde(rename = "scroll-lock")]
     pub scroll_element: Option<ScrollElement>,
+    /// how arrays of imported files are merged (default: `replace`)
+    #[serde(rename = "merge-arrays")]
+    pub merge_arrays: Option<ArrayMerge>,
+    /// data files available to templates as `data.<name>`:
+    /// name => data file relative to the project folder
+    pub data: Option<HashMap<String, String>>,
 }
 
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+/// Merge strategy for arrays present in both the manifest and an import
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ArrayMerge {
+    #[default]
+    Replace,
+    Append,
+}
+
+/// Recursively merge `source` into `target`: tables are merged key by key,
+/// arrays are replaced or appended and any other `source` value replaces
+/// the `target` value
+pub fn merge_toml(target: &mut toml::Value, source: toml::Value, arrays: ArrayMerge) {
+    match (target, source) {
+        (toml::Value::Table(target), toml::Value::Table(source)) => {
+            for (key, value) in source {
+                match target.get_mut(&key) {
+                    Some(existing) => merge_toml(existing, value, arrays),
+                    None => {
+                        target.insert(key, value);
+                    }
+                }
+            }
+        }
+        (toml::Value::Array(target), toml::Value::Array(source))
+            if arrays == ArrayMerge::Append =>
+        {
+            target.extend(source);
+        }
+        (target, source) => *target = source,
+    }
+}
+
+/// Replace `${VAR}` and `${VAR:-default}` references to environment variables
+/// in all string values; the default is used if the variable is unset or empty
+/// and `$${` yields a literal `${`
+pub fn interpolate_env(value: &mut toml::Value) -> std::result::Result<(), String> {
+    match value {
+        toml::Value::String(text) if text.contains('$') => {
+            let mut result = String::new();
+            let mut last = 0;
+            for captures in ENV_REFERENCE.captures_iter(text) {
+                let matched = captures.get(0).unwrap();
+                result.push_str(&text[last..matched.start()]);
+                last = matched.end();
+                let Some(name) = captures.get(1) else {
+                    result.push_str("${");
+                    continue;
+                };
+                match (std::env::var(name.as_str()), captures.get(3)) {
+                    (Ok(var), _) if !var.is_empty() => result.push_str(&var),
+                    (_, Some(default)) => result.push_str(default.as_str()),
+                    (Ok(var), None) => result.push_str(&var),
+                    (Err(_), None) => {
+                        return Err(format!(
+                            "Environment variable `{}` is not set (use `${{{}:-default}}` to provide a default value)",
+                            name.as_str(),
+                            name.as_str()
+                        ))
+                    }
+                }
+            }
+            result.push_str(&text[last..]);
+            *text = result;
+        }
+        toml::Value::Array(array) => {
+            for value in array.iter_mut() {
+                interpolate_env(value)?;
+            }
+        }
+        toml::Value::Table(table) => {
+            for (_, value) in table.iter_mut() {
+                interpolate_env(value)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Set the value at a dotted `path` (i.e. `site.base_url`), creating missing
+/// tables; `value` is parsed as a TOML value (`true`, `8080`, `["a", "b"]`)
+/// and used as a plain string if it is not one
+pub fn define_toml(target: &mut toml::Value, path: &str, value: &str) -> Result<()> {
+    let value = toml::from_str::<toml::Table>(&format!("value = {value}"))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(value.to_string()));
+
+    let keys = path.split('.').map(str::trim).collect::<Vec<_>>();
+    if keys.iter().any(|key| key.is_empty()) {
+        return Err(format!("Invalid definition key `{path}`").into());
+    }
+    let (key, parents) = keys.split_last().unwrap();
+    let mut table = target
+        .as_table_mut()
+        .ok_or("the manifest is not a TOML table")?;
+    for (index, parent) in parents.iter().enumerate() {
+        table = table
+            .entry(parent.to_string())
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+            .as_table_mut()
+            .ok_or_else(|| {
+                format!(
+                    "Unable to define `{path}`: `{}` is not a table",
+                    keys[..=index].join(".")
+                )
+            })?;
+    }
+    table.insert(key.to_string(), value);
+    Ok(())
+}
+
+/// `[serve]` section of the manifest, used by `wahoo serve`
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct ServeSettings {
+    /// a host or a list of hosts, i.e. `["127.0.0.1", "::1"]`
+    pub host: Option<Hosts>,
+    pub port: Option<u16>,
+    /// path prefix => backend url, i.e. `"/api" = "http://127.0.0.1:3000"`;
+    /// a backend url path replaces the prefix
+    pub proxy: Option<HashMap<String, String>>,
+    pub headers: Option<Vec<HeaderRule>>,
+    pub redirects: Option<Vec<RedirectRule>>,
+    /// page served for unmatched routes (single-page applications)
+    pub fallback: Option<String>,
+}
+
+/// `serve.host` value
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum Hosts {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Hosts {
+    pub fn to_vec(&self) -> Vec<String> {
+        match self {
+            Hosts::One(host) => vec![host.clone()],
+            Hosts::Many(hosts) => hosts.clone(),
+        }
+    }
+}
+
+/// `[[serve.headers]]` entry: response headers for paths matching a glob
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct HeaderRule {
+    #[serde(rename = "for")]
+    pub path: String,
+    pub values: HashMap<String, String>,
+}
+
+/// `[[serve.redirects]]` entry
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct RedirectRule {
+    pub from: String,
+    pub to: String,
+    /// HTTP status code (default: 301); 200 rewrites the request
+    pub status: Option<u16>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct ScrollElement {
     pub id: Option<String>,
     pub class: Option<String>,
     pub tag: Option<String>,
 }
 
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct DataMap {
     pub data: String,
     pub templates: String,
@@ -199,7 +602,8 @@ pub struct Section {
     // pub templates: Option<String>,
 }
 
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct SectionSettings {
     /// Section title
     pub title: Option<String>,
@@ -210,7 +614,16 @@ pub struct SectionSettings {
     pub index: Option<String>,
     pub template: Option<String>,
     /// render each file entry of `Self::folder` directory via `Self::template`
+    /// (or via the `settings.markdown` layout if there is no template)
     pub enumerate: Option<bool>,
+    /// Output path of each file, relative to the language folder, where
+    /// `{section}` is the section name, `{path}` the subfolder of the file
+    /// and `{slug}` its slug (default: `{section}/{path}{slug}.html`)
+    pub permalink: Option<String>,
+    /// Derive slugs from file names by lowercasing them and replacing
+    /// other characters than letters and digits with `-`; a `slug`
+    /// front matter value always takes precedence (default: `false`)
+    pub slugify: Option<bool>,
 }
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
@@ -232,3 +645,211 @@ pub struct SectionReference {
 //         }
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn toml(text: &str) -> toml::Value {
+        toml::from_str(text).unwrap()
+    }
+
+    /// Create an empty folder for the test `name` under the temp folder
+    fn folder(name: &str) -> PathBuf {
+        let folder = std::env::temp_dir().join(format!("wahoo-{}-{name}", std::process::id()));
+        std::fs::remove_dir_all(&folder).ok();
+        std::fs::create_dir_all(&folder).unwrap();
+        folder
+    }
+
+    async fn import(folder: &Path, file: &str) -> Result<toml::Value> {
+        Manifest::load_import(
+            folder,
+            file,
+            ArrayMerge::Replace,
+            &mut vec![],
+            &mut vec![],
+            &mut vec![],
+        )
+        .await
+    }
+
+    #[test]
+    fn merge_replaces_arrays() {
+        let mut target = toml("list = [1, 2]\nvalue = 'a'");
+        merge_toml(
+            &mut target,
+            toml("list = [3]\nvalue = 'b'"),
+            ArrayMerge::Replace,
+        );
+        assert_eq!(target, toml("list = [3]\nvalue = 'b'"));
+    }
+
+    #[test]
+    fn merge_appends_arrays() {
+        let mut target = toml("list = [1, 2]");
+        merge_toml(&mut target, toml("list = [3]"), ArrayMerge::Append);
+        assert_eq!(target, toml("list = [1, 2, 3]"));
+    }
+
+    #[test]
+    fn merge_nested_tables() {
+        let mut target = toml("[a.b]\nx = 1\ny = 2\n[c]\nz = 3");
+        let source = toml("[a.b]\ny = 20\nw = 40\n[d]\nv = 5");
+        merge_toml(&mut target, source, ArrayMerge::Replace);
+        assert_eq!(
+            target,
+            toml("[a.b]\nx = 1\ny = 20\nw = 40\n[c]\nz = 3\n[d]\nv = 5")
+        );
+    }
+
+    #[test]
+    fn merge_replaces_mismatched_types() {
+        let mut target = toml("a = [1]\nb = { x = 1 }");
+        merge_toml(
+            &mut target,
+            toml("a = { x = 1 }\nb = 2"),
+            ArrayMerge::Append,
+        );
+        assert_eq!(target, toml("a = { x = 1 }\nb = 2"));
+    }
+
+    fn interpolate(text: &str) -> std::result::Result<String, String> {
+        let mut value = toml::Value::String(text.to_string());
+        interpolate_env(&mut value)?;
+        Ok(value.as_str().unwrap().to_string())
+    }
+
+    #[test]
+    fn interpolates_environment_variables() {
+        std::env::set_var("WAHOO_TEST_SET", "value");
+        std::env::set_var("WAHOO_TEST_EMPTY", "");
+        std::env::remove_var("WAHOO_TEST_UNSET");
+
+        assert_eq!(interpolate("a ${WAHOO_TEST_SET} b").unwrap(), "a value b");
+        assert_eq!(interpolate("${WAHOO_TEST_SET:-default}").unwrap(), "value");
+        assert_eq!(
+            interpolate("${WAHOO_TEST_UNSET:-default}").unwrap(),
+            "default"
+        );
+        assert_eq!(
+            interpolate("${WAHOO_TEST_EMPTY:-default}").unwrap(),
+            "default"
+        );
+        assert_eq!(interpolate("${WAHOO_TEST_UNSET:-}").unwrap(), "");
+        assert_eq!(interpolate("${WAHOO_TEST_EMPTY}").unwrap(), "");
+        assert_eq!(
+            interpolate("$$ $WAHOO_TEST_SET").unwrap(),
+            "$$ $WAHOO_TEST_SET"
+        );
+    }
+
+    #[test]
+    fn escaped_references_are_literal() {
+        std::env::set_var("WAHOO_TEST_ESCAPE", "value");
+        assert_eq!(
+            interpolate("$${WAHOO_TEST_ESCAPE}").unwrap(),
+            "${WAHOO_TEST_ESCAPE}"
+        );
+        assert_eq!(
+            interpolate("$${WAHOO_TEST_ESCAPE} ${WAHOO_TEST_ESCAPE}").unwrap(),
+            "${WAHOO_TEST_ESCAPE} value"
+        );
+    }
+
+    #[test]
+    fn unset_variables_without_default_are_errors() {
+        std::env::remove_var("WAHOO_TEST_MISSING");
+        let err = interpolate("${WAHOO_TEST_MISSING}").unwrap_err();
+        assert!(err.contains("`WAHOO_TEST_MISSING` is not set"), "{err}");
+    }
+
+    #[test]
+    fn interpolates_nested_values() {
+        std::env::set_var("WAHOO_TEST_NESTED", "value");
+        let mut value = toml("list = ['${WAHOO_TEST_NESTED}']\n[a.b]\nc = '${WAHOO_TEST_NESTED}'");
+        interpolate_env(&mut value).unwrap();
+        assert_eq!(value, toml("list = ['value']\n[a.b]\nc = 'value'"));
+    }
+
+    #[test]
+    fn defines_typed_values_at_dotted_paths() {
+        let mut target = toml(
+            "[site]
+name = 'a'",
+        );
+        define_toml(&mut target, "site.port", "8080").unwrap();
+        define_toml(&mut target, "site.draft", "true").unwrap();
+        define_toml(&mut target, "site.tags", r#"["a", "b"]"#).unwrap();
+        define_toml(&mut target, "site.name", "b").unwrap();
+        define_toml(&mut target, "a.b.c", "'d'").unwrap();
+        assert_eq!(
+            target,
+            toml(
+                "[site]\nname = 'b'\nport = 8080\ndraft = true\ntags = ['a', 'b']\n[a.b]\nc = 'd'"
+            )
+        );
+    }
+
+    #[test]
+    fn defines_unparsable_values_as_strings() {
+        let mut target = toml("");
+        define_toml(&mut target, "site.base_url", "https://example.com/").unwrap();
+        define_toml(&mut target, "site.title", "[unclosed").unwrap();
+        assert_eq!(
+            target,
+            toml("[site]\nbase_url = 'https://example.com/'\ntitle = '[unclosed'")
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_definition_paths() {
+        let mut target = toml("[site]\nname = 'a'");
+        assert!(define_toml(&mut target, "site..name", "b").is_err());
+        assert!(define_toml(&mut target, "", "b").is_err());
+        let err = define_toml(&mut target, "site.name.first", "b")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("`site.name` is not a table"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn nested_imports_are_merged_without_their_import_list() {
+        let folder = folder("nested-import");
+        std::fs::create_dir(folder.join("nested")).unwrap();
+        std::fs::write(
+            folder.join("a.toml"),
+            "[settings]\nimport = ['nested/b.toml']\n[site]\nname = 'a'\ntitle = 'a'",
+        )
+        .unwrap();
+        std::fs::write(folder.join("nested/b.toml"), "[site]\ntitle = 'b'").unwrap();
+
+        let toml = import(&folder, "a.toml").await.unwrap();
+        std::fs::remove_dir_all(&folder).ok();
+        assert_eq!(toml["site"]["name"].as_str(), Some("a"));
+        assert_eq!(toml["site"]["title"].as_str(), Some("b"));
+        assert!(toml["settings"].get("import").is_none());
+    }
+
+    #[tokio::test]
+    async fn import_cycles_are_detected() {
+        let folder = folder("import-cycle");
+        std::fs::write(folder.join("a.toml"), "[settings]\nimport = ['b.toml']").unwrap();
+        std::fs::write(folder.join("b.toml"), "[settings]\nimport = ['a.toml']").unwrap();
+
+        let err = import(&folder, "a.toml").await.unwrap_err().to_string();
+        std::fs::remove_dir_all(&folder).ok();
+        assert!(err.contains("Import cycle detected"), "{err}");
+        assert!(err.contains("a.toml` -> `"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn self_import_is_a_cycle() {
+        let folder = folder("self-import");
+        std::fs::write(folder.join("a.toml"), "[settings]\nimport = ['a.toml']").unwrap();
+
+        let err = import(&folder, "a.toml").await.unwrap_err().to_string();
+        std::fs::remove_dir_all(&folder).ok();
+        assert!(err.contains("Import cycle detected"), "{err}");
+    }
+}
@@ -163,3 +163,13 @@ pub fn markdown_to_html(str: &str, open_external_in_new_window: bool) -> String
 
     html_output
 }
+
+/// Number of words of the markdown text, excluding html comments (front matter)
+pub fn markdown_word_count(str: &str) -> usize {
+    Parser::new_ext(str, Options::empty())
+        .map(|event| match event {
+            Event::Text(text) | Event::Code(text) => text.split_whitespace().count(),
+            _ => 0,
+        })
+        .sum()
+}
@@ -4,12 +4,16 @@ pub use crate:: {
     result::*,
     manifest::*,
     context::*,
+    dependencies::*,
     sink::*,
     builder::*,
+    collection::*,
     log::*,
     utils::*,
     filter::*,
-    markdown::*
+    markdown::*,
+    theme::*,
+    workspace::*
 };
 
 pub use async_std::fs;
@@ -0,0 +1,392 @@
+use crate::prelude::*;
+use async_std::net::TcpStream;
+use async_tungstenite::tungstenite::client::IntoClientRequest;
+use futures::{future, FutureExt, SinkExt, StreamExt};
+use tide::http::{headers::HeaderName, Method, Url};
+use tide::{Response, StatusCode};
+use tide_websockets::{WebSocket, WebSocketConnection};
+
+/// Headers that apply to a single connection and must not be forwarded
+const HOP_BY_HOP_HEADERS: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    "host",
+];
+
+const PROXY_METHODS: &[Method] = &[
+    Method::Get,
+    Method::Head,
+    Method::Post,
+    Method::Put,
+    Method::Patch,
+    Method::Delete,
+    Method::Options,
+];
+
+/// Headers forwarded to the backend when opening a websocket connection
+const WEBSOCKET_HEADERS: &[&str] = &["authorization", "cookie", "origin", "user-agent"];
+
+fn is_hop_by_hop(name: &HeaderName) -> bool {
+    HOP_BY_HOP_HEADERS.contains(&name.as_str().to_lowercase().as_str())
+}
+
+/// `[serve.proxy]` rule forwarding requests under `prefix` to a backend server
+#[derive(Debug, Clone)]
+pub struct Proxy {
+    pub prefix: String,
+    target: Url,
+}
+
+impl Proxy {
+    pub fn try_new(prefix: &str, target: &str) -> Result<Proxy> {
+        let target = Url::parse(target)
+            .map_err(|err| format!("Invalid proxy target `{target}` for `{prefix}`: {err}"))?;
+        if target.scheme() != "http" {
+            return Err(format!(
+                "Unsupported proxy target `{target}` for `{prefix}`: only `http://` backends are supported"
+            )
+            .into());
+        }
+
+        let prefix = format!("/{}", prefix.trim_matches('/'));
+        Ok(Proxy { prefix, target })
+    }
+
+    /// Create proxies from `[serve.proxy]` rules, longest prefix first
+    pub fn from_settings(serve: &ServeSettings) -> Result<Vec<Proxy>> {
+        let mut proxies = serve
+            .proxy
+            .iter()
+            .flatten()
+            .map(|(prefix, target)| Proxy::try_new(prefix, target))
+            .collect::<Result<Vec<_>>>()?;
+        proxies.sort_by_key(|proxy| std::cmp::Reverse(proxy.prefix.len()));
+        Ok(proxies)
+    }
+
+    pub fn is_match(&self, path: &str) -> bool {
+        path == self.prefix
+            || self.prefix == "/"
+            || path
+                .strip_prefix(&self.prefix)
+                .map(|rest| rest.starts_with('/'))
+                .unwrap_or(false)
+    }
+
+    /// Register proxy routes (HTTP and websocket) with the tide app
+    pub fn register(self, app: &mut tide::Server<()>) {
+        let routes = if self.prefix == "/" {
+            vec!["/*".to_string()]
+        } else {
+            vec![self.prefix.clone(), format!("{}/*", self.prefix)]
+        };
+
+        for route in routes {
+            let websocket = self.clone();
+            let mut route = app.at(&route);
+            route.with(WebSocket::new(
+                move |request: tide::Request<()>, stream: WebSocketConnection| {
+                    let proxy = websocket.clone();
+                    async move { proxy.tunnel(request, stream).await }
+                },
+            ));
+
+            // registered per method, as tide matches method-specific
+            // routes (i.e. the static `GET /*`) before `all()` routes
+            for method in PROXY_METHODS {
+                let http = self.clone();
+                route.method(*method, move |request: tide::Request<()>| {
+                    let proxy = http.clone();
+                    async move { proxy.forward(request).await }
+                });
+            }
+        }
+    }
+
+    /// Url on the backend server for the incoming request url; the prefix
+    /// is replaced by the target path if the target has one (i.e. `/api/users`
+    /// is forwarded to `http://127.0.0.1:3000/v1/users` for a
+    /// `"/api" = "http://127.0.0.1:3000/v1"` rule) and kept otherwise
+    fn url(&self, url: &Url) -> Url {
+        let mut target = self.target.clone();
+        let base = self.target.path().trim_end_matches('/');
+        let path = if base.is_empty() || self.prefix == "/" {
+            format!("{base}{}", url.path())
+        } else {
+            let rest = url.path().strip_prefix(&self.prefix).unwrap_or(url.path());
+            format!("{base}{rest}")
+        };
+        target.set_path(&path);
+        target.set_query(url.query());
+        target
+    }
+
+    pub fn target(&self) -> &Url {
+        &self.target
+    }
+
+    fn address(&self) -> String {
+        format!(
+            "{}:{}",
+            self.target.host_str().unwrap_or("127.0.0.1"),
+            self.target.port_or_known_default().unwrap_or(80)
+        )
+    }
+
+    async fn forward(&self, mut request: tide::Request<()>) -> tide::Result {
+        let url = self.url(request.url());
+        log_trace!("Proxy", "{} {} => {}", request.method(), request.url(), url);
+
+        let mut outgoing = tide::http::Request::new(request.method(), url);
+        for (name, values) in request.iter() {
+            if !is_hop_by_hop(name) {
+                for value in values.iter() {
+                    outgoing.append_header(name, value.as_str());
+                }
+            }
+        }
+
+        outgoing.insert_header("host", self.address());
+        if let Some(host) = request.host() {
+            outgoing.insert_header("x-forwarded-host", host);
+        }
+        if let Some(remote) = request.remote() {
+            outgoing.insert_header("x-forwarded-for", remote);
+        }
+        outgoing.insert_header("x-forwarded-proto", request.url().scheme());
+
+        if request.method() != Method::Get && request.method() != Method::Head {
+            outgoing.set_body(request.take_body());
+        }
+
+        let stream = match TcpStream::connect(self.address()).await {
+            Ok(stream) => stream,
+            Err(err) => {
+                log_warn!("Proxy", "Unable to connect to `{}`: {err}", self.target);
+                return Ok(Response::builder(StatusCode::BadGateway)
+                    .body(format!("Unable to connect to `{}`: {err}", self.target))
+                    .build());
+            }
+        };
+
+        let mut incoming = match async_h1::connect(stream, outgoing).await {
+            Ok(incoming) => incoming,
+            Err(err) => {
+                log_warn!("Proxy", "Invalid response from `{}`: {err}", self.target);
+                return Ok(Response::builder(StatusCode::BadGateway)
+                    .body(format!("Invalid response from `{}`: {err}", self.target))
+                    .build());
+            }
+        };
+        let mut response = Response::new(incoming.status());
+        for (name, values) in incoming.iter() {
+            if !is_hop_by_hop(name) && name.as_str().to_lowercase() != "content-length" {
+                for value in values.iter() {
+                    response.append_header(name, value.as_str());
+                }
+            }
+        }
+        response.set_body(incoming.take_body());
+
+        Ok(response)
+    }
+
+    async fn tunnel(
+        &self,
+        request: tide::Request<()>,
+        stream: WebSocketConnection,
+    ) -> tide::Result<()> {
+        let mut url = self.url(request.url());
+        url.set_scheme("ws").ok();
+        log_trace!("Proxy", "websocket {} => {}", request.url(), url);
+
+        let mut outgoing = url.as_str().into_client_request()?;
+        for name in WEBSOCKET_HEADERS {
+            if let Some(value) = request.header(*name) {
+                if let Ok(value) = value.as_str().parse() {
+                    outgoing.headers_mut().insert(*name, value);
+                }
+            }
+        }
+
+        let (backend, _) = match async_tungstenite::async_std::connect_async(outgoing).await {
+            Ok(backend) => backend,
+            Err(err) => {
+                log_warn!("Proxy", "Unable to connect to `{url}`: {err}");
+                return Ok(());
+            }
+        };
+        let (mut backend_sink, mut backend_stream) = backend.split();
+
+        let mut client = stream.clone();
+        let upstream = async move {
+            while let Some(Ok(message)) = client.next().await {
+                if backend_sink.send(message).await.is_err() {
+                    break;
+                }
+            }
+            backend_sink.close().await.ok();
+        };
+
+        let downstream = async move {
+            while let Some(Ok(message)) = backend_stream.next().await {
+                if stream.send(message).await.is_err() {
+                    break;
+                }
+            }
+        };
+
+        future::select(upstream.boxed(), downstream.boxed()).await;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::net::TcpListener;
+    use tide::http::{Request, Url};
+
+    /// Start `app` on a free local port, returning its address
+    async fn listen(app: tide::Server<()>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        async_std::task::spawn(app.listen(listener));
+        address
+    }
+
+    /// Backend echoing the request path and headers
+    async fn stub() -> String {
+        let mut app = tide::new();
+        let mut route = app.at("/*");
+        route.with(WebSocket::new(
+            |_request: tide::Request<()>, mut stream: WebSocketConnection| async move {
+                while let Some(Ok(message)) = stream.next().await {
+                    stream.send(message).await?;
+                }
+                Ok(())
+            },
+        ));
+        route.all(|request: tide::Request<()>| async move {
+            let mut response = Response::new(StatusCode::Ok);
+            response.insert_header("x-backend", "stub");
+            let mut headers = request
+                .iter()
+                .map(|(name, values)| format!("{name}: {}", values.as_str()))
+                .collect::<Vec<_>>();
+            headers.sort();
+            let query = request.url().query().map(|query| format!("?{query}"));
+            response.set_body(format!(
+                "{}{}\n{}",
+                request.url().path(),
+                query.unwrap_or_default(),
+                headers.join("\n")
+            ));
+            Ok(response)
+        });
+        listen(app).await
+    }
+
+    fn app(prefix: &str, target: &str) -> tide::Server<()> {
+        let mut app = tide::new();
+        Proxy::try_new(prefix, target).unwrap().register(&mut app);
+        app
+    }
+
+    async fn get(app: &tide::Server<()>, url: &str, headers: &[(&str, &str)]) -> (u16, String) {
+        let mut request = Request::new(Method::Get, Url::parse(url).unwrap());
+        for (name, value) in headers {
+            request.insert_header(*name, *value);
+        }
+        let mut response: tide::http::Response = app.respond(request).await.unwrap();
+        let body = response.body_string().await.unwrap();
+        (response.status() as u16, body)
+    }
+
+    #[tokio::test]
+    async fn forwards_headers() {
+        let backend = stub().await;
+        let app = app("/api", &format!("http://{backend}"));
+        let (status, body) = get(
+            &app,
+            "http://localhost/api/users",
+            &[("x-custom", "1"), ("connection", "keep-alive")],
+        )
+        .await;
+        assert_eq!(status, 200);
+        assert!(body.contains("x-custom: 1"));
+        assert!(body.contains("x-forwarded-host: localhost"));
+        assert!(body.contains(&format!("host: {backend}")));
+        assert!(!body.contains("connection: keep-alive"));
+    }
+
+    #[tokio::test]
+    async fn returns_backend_headers() {
+        let backend = stub().await;
+        let app = app("/api", &format!("http://{backend}"));
+        let request = Request::new(Method::Get, Url::parse("http://localhost/api").unwrap());
+        let response: tide::http::Response = app.respond(request).await.unwrap();
+        assert_eq!(response.header("x-backend").unwrap().as_str(), "stub");
+    }
+
+    #[tokio::test]
+    async fn keeps_prefix_without_target_path() {
+        let backend = stub().await;
+        let app = app("/api", &format!("http://{backend}"));
+        let (_, body) = get(&app, "http://localhost/api/users?page=2", &[]).await;
+        assert!(body.starts_with("/api/users?page=2\n"));
+    }
+
+    #[tokio::test]
+    async fn replaces_prefix_with_target_path() {
+        let backend = stub().await;
+        let app = app("/api/", &format!("http://{backend}/v1/"));
+        let (_, body) = get(&app, "http://localhost/api/users?page=2", &[]).await;
+        assert!(body.starts_with("/v1/users?page=2\n"));
+        let (_, body) = get(&app, "http://localhost/api", &[]).await;
+        assert!(body.starts_with("/v1\n"));
+    }
+
+    #[tokio::test]
+    async fn tunnels_websockets() {
+        let backend = stub().await;
+        let address = listen(app("/api", &format!("http://{backend}"))).await;
+        let (mut socket, _) =
+            async_tungstenite::async_std::connect_async(format!("ws://{address}/api/socket"))
+                .await
+                .unwrap();
+        socket
+            .send(async_tungstenite::tungstenite::Message::Text("ping".into()))
+            .await
+            .unwrap();
+        let message = socket.next().await.unwrap().unwrap();
+        assert_eq!(message.into_text().unwrap(), "ping");
+    }
+
+    #[tokio::test]
+    async fn unreachable_backend_is_bad_gateway() {
+        // a port that was just released has nothing listening on it
+        let address = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let app = app("/api", &format!("http://{address}"));
+        let (status, _) = get(&app, "http://localhost/api/users", &[]).await;
+        assert_eq!(status, 502);
+    }
+
+    #[test]
+    fn rejects_unsupported_targets() {
+        assert!(Proxy::try_new("/api", "https://127.0.0.1:3000").is_err());
+        assert!(Proxy::try_new("/api", "ws://127.0.0.1:3000").is_err());
+        assert!(Proxy::try_new("/api", "not a url").is_err());
+    }
+}
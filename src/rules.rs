@@ -0,0 +1,343 @@
+use crate::prelude::*;
+use tide::http::{headers::LOCATION, Url};
+use tide::utils::async_trait;
+use tide::{Middleware, Next, Request, Response, StatusCode};
+
+/// A `[[serve.redirects]]` rule; a trailing `*` in `from`
+/// is substituted for `:splat` in `to`
+#[derive(Debug, Clone)]
+pub struct Redirect {
+    from: String,
+    to: String,
+    status: u16,
+}
+
+impl Redirect {
+    fn target(&self, path: &str) -> Option<String> {
+        if let Some(prefix) = self.from.strip_suffix('*') {
+            path.strip_prefix(prefix)
+                .map(|splat| self.to.replace(":splat", splat))
+        } else if path == self.from {
+            Some(self.to.clone())
+        } else {
+            None
+        }
+    }
+}
+
+/// A `[[serve.headers]]` rule
+#[derive(Debug, Clone)]
+pub struct Headers {
+    path: String,
+    matcher: GlobMatcher,
+    values: Vec<(String, String)>,
+}
+
+/// Custom headers, redirects and SPA fallback from the `[serve]` manifest section,
+/// applied by the dev server and exported as `_headers`/`_redirects` during build
+#[derive(Debug, Clone, Default)]
+pub struct Rules {
+    headers: Vec<Headers>,
+    redirects: Vec<Redirect>,
+    fallback: Option<String>,
+}
+
+impl Rules {
+    pub fn try_new(serve: &ServeSettings) -> Result<Rules> {
+        let mut headers = vec![];
+        for rule in serve.headers.iter().flatten() {
+            let matcher = Glob::new(&rule.path)?.compile_matcher();
+            let mut values = rule
+                .values
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect::<Vec<_>>();
+            values.sort();
+            headers.push(Headers {
+                path: rule.path.clone(),
+                matcher,
+                values,
+            });
+        }
+
+        let mut redirects = vec![];
+        for rule in serve.redirects.iter().flatten() {
+            let status = rule.status.unwrap_or(301);
+            // 200 rewrites, any other status redirects
+            if status != 200 && !(300..400).contains(&status)
+                || StatusCode::try_from(status).is_err()
+            {
+                return Err(format!(
+                    "Invalid status `{status}` for redirect `{}` (expected 200 or a 3xx status)",
+                    rule.from
+                )
+                .into());
+            }
+            redirects.push(Redirect {
+                from: rule.from.clone(),
+                to: rule.to.clone(),
+                status,
+            });
+        }
+
+        let fallback = serve
+            .fallback
+            .as_ref()
+            .map(|fallback| format!("/{}", fallback.trim_start_matches('/')));
+
+        Ok(Rules {
+            headers,
+            redirects,
+            fallback,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.headers.is_empty() && self.redirects.is_empty() && self.fallback.is_none()
+    }
+
+    /// SPA fallback page served for unmatched routes
+    pub fn fallback(&self) -> Option<&str> {
+        self.fallback.as_deref()
+    }
+
+    /// Headers to be sent with the response to `path`
+    pub fn headers<'a>(&'a self, path: &'a str) -> impl Iterator<Item = &'a (String, String)> {
+        self.headers
+            .iter()
+            .filter(move |headers| headers.matcher.is_match(path))
+            .flat_map(|headers| headers.values.iter())
+    }
+
+    /// Redirect target and status code for `path`
+    pub fn redirect(&self, path: &str) -> Option<(String, u16)> {
+        self.redirects
+            .iter()
+            .find_map(|redirect| redirect.target(path).map(|to| (to, redirect.status)))
+    }
+
+    /// Rules in the `_headers` file format
+    pub fn headers_file(&self) -> String {
+        let mut text = String::new();
+        for headers in self.headers.iter() {
+            text += &format!("{}\n", headers.path);
+            for (name, value) in headers.values.iter() {
+                text += &format!("  {name}: {value}\n");
+            }
+        }
+        text
+    }
+
+    /// Rules in the `_redirects` file format
+    pub fn redirects_file(&self) -> String {
+        let mut text = String::new();
+        for redirect in self.redirects.iter() {
+            text += &format!("{} {} {}\n", redirect.from, redirect.to, redirect.status);
+        }
+        if let Some(fallback) = &self.fallback {
+            text += &format!("/* {fallback} 200\n");
+        }
+        text
+    }
+}
+
+#[async_trait]
+impl<State> Middleware<State> for Rules
+where
+    State: Clone + Send + Sync + 'static,
+{
+    async fn handle(&self, mut request: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let path = request.url().path().to_string();
+
+        if let Some((to, status)) = self.redirect(&path) {
+            if status == 200 {
+                // rewrite: serve the target under the requested url
+                let url: &mut Url = AsMut::<tide::http::Request>::as_mut(&mut request).url_mut();
+                url.set_path(&to);
+            } else {
+                let status = StatusCode::try_from(status)?;
+                let mut response = Response::new(status);
+                response.insert_header(LOCATION, to);
+                return Ok(response);
+            }
+        }
+
+        let mut response = next.run(request).await;
+        for (name, value) in self.headers(&path) {
+            response.insert_header(name.as_str(), value.as_str());
+        }
+
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tide::http::{Method, Request as HttpRequest};
+
+    fn rules(text: &str) -> Result<Rules> {
+        let serve: ServeSettings = toml::from_str(text).unwrap();
+        Rules::try_new(&serve)
+    }
+
+    /// Rules applied in front of an app echoing the requested path
+    async fn get(rules: Rules, path: &str) -> tide::http::Response {
+        let mut inner = tide::new();
+        inner
+            .at("*")
+            .get(|request: Request<()>| async move { Ok(request.url().path().to_string()) });
+        let mut app = tide::new();
+        app.with(rules);
+        app.at("/").all(inner.clone());
+        app.at("/*").all(inner);
+
+        let url = Url::parse(&format!("http://localhost{path}")).unwrap();
+        app.respond(HttpRequest::new(Method::Get, url))
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn substitutes_splats() {
+        let rules = rules(
+            r#"
+            [[redirects]]
+            from = "/blog/*"
+            to = "/posts/:splat"
+            [[redirects]]
+            from = "/old/*"
+            to = "/new/:splat/:splat"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            rules.redirect("/blog/2024/hello.html"),
+            Some(("/posts/2024/hello.html".to_string(), 301))
+        );
+        assert_eq!(rules.redirect("/blog/"), Some(("/posts/".to_string(), 301)));
+        assert_eq!(
+            rules.redirect("/old/a"),
+            Some(("/new/a/a".to_string(), 301))
+        );
+        assert_eq!(rules.redirect("/blog"), None);
+    }
+
+    #[test]
+    fn matches_exact_paths_without_placeholders() {
+        let rules = rules(
+            r#"
+            [[redirects]]
+            from = "/about"
+            to = "/about/:splat"
+            status = 302
+            "#,
+        )
+        .unwrap();
+        // `:splat` is only substituted for a trailing `*`
+        assert_eq!(
+            rules.redirect("/about"),
+            Some(("/about/:splat".to_string(), 302))
+        );
+        assert_eq!(rules.redirect("/about/team"), None);
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let rules = rules(
+            r#"
+            [[redirects]]
+            from = "/docs/intro"
+            to = "/guide/"
+            status = 302
+            [[redirects]]
+            from = "/docs/*"
+            to = "/manual/:splat"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            rules.redirect("/docs/intro"),
+            Some(("/guide/".to_string(), 302))
+        );
+        assert_eq!(
+            rules.redirect("/docs/setup"),
+            Some(("/manual/setup".to_string(), 301))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_statuses() {
+        for status in [0, 100, 204, 404, 500, 999] {
+            let text = format!("[[redirects]]\nfrom = \"/a\"\nto = \"/b\"\nstatus = {status}");
+            let err = rules(&text).unwrap_err().to_string();
+            assert!(err.contains(&format!("Invalid status `{status}`")), "{err}");
+        }
+        for status in [200, 301, 302, 307, 308] {
+            let text = format!("[[redirects]]\nfrom = \"/a\"\nto = \"/b\"\nstatus = {status}");
+            assert!(rules(&text).is_ok(), "{status}");
+        }
+    }
+
+    #[test]
+    fn exports_rules_files() {
+        let rules = rules(
+            r#"
+            fallback = "index.html"
+            [[headers]]
+            for = "/assets/*"
+            values = { "Cache-Control" = "max-age=3600", "X-Frame-Options" = "DENY" }
+            [[redirects]]
+            from = "/blog/*"
+            to = "/posts/:splat"
+            [[redirects]]
+            from = "/app"
+            to = "/app/index.html"
+            status = 200
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            rules.headers_file(),
+            "/assets/*\n  Cache-Control: max-age=3600\n  X-Frame-Options: DENY\n"
+        );
+        assert_eq!(
+            rules.redirects_file(),
+            "/blog/* /posts/:splat 301\n/app /app/index.html 200\n/* /index.html 200\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn rewrites_and_redirects_requests() {
+        let rules = rules(
+            r#"
+            [[headers]]
+            for = "/app/*"
+            values = { "X-App" = "1" }
+            [[redirects]]
+            from = "/app/*"
+            to = "/index.html"
+            status = 200
+            [[redirects]]
+            from = "/old/*"
+            to = "/new/:splat"
+            status = 307
+            "#,
+        )
+        .unwrap();
+
+        // rewrites are served under the requested url, with its headers
+        let mut response = get(rules.clone(), "/app/settings").await;
+        assert_eq!(response.status(), StatusCode::Ok);
+        assert_eq!(response.body_string().await.unwrap(), "/index.html");
+        assert_eq!(response["X-App"], "1");
+
+        let response = get(rules.clone(), "/old/page.html").await;
+        assert_eq!(response.status(), StatusCode::TemporaryRedirect);
+        assert_eq!(response[LOCATION], "/new/page.html");
+
+        let mut response = get(rules, "/other").await;
+        assert_eq!(response.status(), StatusCode::Ok);
+        assert_eq!(response.body_string().await.unwrap(), "/other");
+    }
+}
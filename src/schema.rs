@@ -0,0 +1,360 @@
+use crate::prelude::*;
+use schemars::schema::{InstanceType, RootSchema, Schema, SchemaObject, SingleOrVec};
+use schemars::JsonSchema;
+use serde::de::{self, Deserializer, MapAccess, SeqAccess, Visitor};
+use std::fmt;
+use std::ops::Range;
+
+/// Typed view of `wahoo.toml` and of the files it imports; other
+/// top-level tables hold site data and are not validated
+#[derive(Debug, Deserialize, JsonSchema)]
+#[schemars(title = "wahoo.toml")]
+pub struct ManifestFile {
+    pub settings: Option<Settings>,
+    pub serve: Option<ServeSettings>,
+    /// overlays merged over the manifest for the `--env` build environment
+    pub env: Option<HashMap<String, ManifestFile>>,
+    pub workspace: Option<WorkspaceSettings>,
+}
+
+/// Typed view of a section file listed in `settings.sections`
+#[derive(Debug, Deserialize, JsonSchema)]
+#[schemars(title = "wahoo section")]
+pub struct SectionFile {
+    pub settings: Option<SectionSettings>,
+}
+
+/// Typed view of a theme's `theme.toml`; other top-level
+/// tables hold default site data and are not validated
+#[derive(Debug, Deserialize, JsonSchema)]
+#[schemars(title = "wahoo theme")]
+pub struct ThemeFile {
+    /// parent theme folder, relative to the theme folder
+    pub extends: Option<String>,
+}
+
+/// Kind of TOML file being validated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    Manifest,
+    Section,
+    Theme,
+}
+
+impl FileKind {
+    pub fn schema(&self) -> RootSchema {
+        match self {
+            FileKind::Manifest => schemars::schema_for!(ManifestFile),
+            FileKind::Section => schemars::schema_for!(SectionFile),
+            FileKind::Theme => schemars::schema_for!(ThemeFile),
+        }
+    }
+}
+
+/// Validate the `text` of a TOML `file`: type errors are returned with their
+/// location, unknown keys of validated tables are returned as warnings
+pub fn validate(file: &Path, text: &str, kind: FileKind) -> Result<Vec<String>> {
+    let typed = match kind {
+        FileKind::Manifest => toml::from_str::<ManifestFile>(text).map(|_| ()),
+        FileKind::Section => toml::from_str::<SectionFile>(text).map(|_| ()),
+        FileKind::Theme => toml::from_str::<ThemeFile>(text).map(|_| ()),
+    };
+    typed.map_err(|err| format!("Error loading `{}`: {err}", file.display()))?;
+
+    let node: Node = toml::from_str(text)?;
+    let validator = Validator {
+        file,
+        text,
+        schema: kind.schema(),
+        warnings: vec![],
+    };
+    Ok(validator.validate(&node))
+}
+
+/// TOML value retaining the location of its keys
+enum Node {
+    Table(Vec<(Key, Node)>),
+    Array(Vec<Node>),
+    Value,
+}
+
+impl<'de> Deserialize<'de> for Node {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Node, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct NodeVisitor;
+
+        impl<'de> Visitor<'de> for NodeVisitor {
+            type Value = Node;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a TOML value")
+            }
+
+            fn visit_map<A>(self, mut map: A) -> std::result::Result<Node, A::Error>
+            where
+                A: MapAccess<'de>,
+            {
+                let mut entries = vec![];
+                while let Some(key) = map.next_key::<Key>()? {
+                    // datetimes are deserialized as a map holding a single
+                    // private (and span-less) key
+                    if key.span.is_none() && key.name == DATETIME_FIELD {
+                        map.next_value::<de::IgnoredAny>()?;
+                        return Ok(Node::Value);
+                    }
+                    entries.push((key, map.next_value()?));
+                }
+                Ok(Node::Table(entries))
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> std::result::Result<Node, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                let mut items = vec![];
+                while let Some(item) = seq.next_element()? {
+                    items.push(item);
+                }
+                Ok(Node::Array(items))
+            }
+
+            fn visit_bool<E: de::Error>(self, _: bool) -> std::result::Result<Node, E> {
+                Ok(Node::Value)
+            }
+
+            fn visit_i64<E: de::Error>(self, _: i64) -> std::result::Result<Node, E> {
+                Ok(Node::Value)
+            }
+
+            fn visit_u64<E: de::Error>(self, _: u64) -> std::result::Result<Node, E> {
+                Ok(Node::Value)
+            }
+
+            fn visit_f64<E: de::Error>(self, _: f64) -> std::result::Result<Node, E> {
+                Ok(Node::Value)
+            }
+
+            fn visit_str<E: de::Error>(self, _: &str) -> std::result::Result<Node, E> {
+                Ok(Node::Value)
+            }
+        }
+
+        deserializer.deserialize_any(NodeVisitor)
+    }
+}
+
+/// Private field names of `toml::Spanned` and `toml::value::Datetime`
+const SPANNED_NAME: &str = "$__serde_spanned_private_Spanned";
+const SPANNED_START: &str = "$__serde_spanned_private_start";
+const SPANNED_END: &str = "$__serde_spanned_private_end";
+const SPANNED_VALUE: &str = "$__serde_spanned_private_value";
+const SPANNED_FIELDS: &[&str] = &[SPANNED_START, SPANNED_END, SPANNED_VALUE];
+const DATETIME_FIELD: &str = "$__toml_private_datetime";
+
+/// Table key along with its location, if the deserializer provides one
+struct Key {
+    name: String,
+    span: Option<Range<usize>>,
+}
+
+impl<'de> Deserialize<'de> for Key {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Key, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct KeyVisitor;
+
+        impl<'de> Visitor<'de> for KeyVisitor {
+            type Value = Key;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a TOML key")
+            }
+
+            fn visit_str<E: de::Error>(self, name: &str) -> std::result::Result<Key, E> {
+                Ok(Key {
+                    name: name.to_string(),
+                    span: None,
+                })
+            }
+
+            fn visit_map<A>(self, mut map: A) -> std::result::Result<Key, A::Error>
+            where
+                A: MapAccess<'de>,
+            {
+                let (mut start, mut end, mut name) = (None, None, None);
+                while let Some(field) = map.next_key::<String>()? {
+                    match field.as_str() {
+                        SPANNED_START => start = Some(map.next_value()?),
+                        SPANNED_END => end = Some(map.next_value()?),
+                        SPANNED_VALUE => name = Some(map.next_value()?),
+                        _ => return Err(de::Error::unknown_field(&field, SPANNED_FIELDS)),
+                    }
+                }
+                Ok(Key {
+                    name: name.ok_or_else(|| de::Error::missing_field(SPANNED_VALUE))?,
+                    span: start.zip(end).map(|(start, end)| start..end),
+                })
+            }
+        }
+
+        deserializer.deserialize_struct(SPANNED_NAME, SPANNED_FIELDS, KeyVisitor)
+    }
+}
+
+struct Validator<'a> {
+    file: &'a Path,
+    text: &'a str,
+    schema: RootSchema,
+    warnings: Vec<String>,
+}
+
+impl<'a> Validator<'a> {
+    fn validate(mut self, node: &Node) -> Vec<String> {
+        let schema = Schema::Object(self.schema.schema.clone());
+        self.check(node, &schema, "");
+        self.warnings
+    }
+
+    fn check(&mut self, node: &Node, schema: &Schema, path: &str) {
+        let Some(schema) = self.resolve(schema, node) else {
+            return;
+        };
+
+        match node {
+            Node::Table(entries) => {
+                let Some(object) = &schema.object else {
+                    return;
+                };
+                for (key, value) in entries {
+                    let key_path = if path.is_empty() {
+                        key.name.clone()
+                    } else {
+                        format!("{path}.{}", key.name)
+                    };
+                    if let Some(property) = object.properties.get(&key.name) {
+                        self.check(value, property, &key_path);
+                    } else {
+                        match object.additional_properties.as_deref() {
+                            Some(Schema::Bool(false)) => {
+                                let suggestion = suggest(&key.name, object.properties.keys());
+                                self.unknown(&key_path, key.span.clone(), suggestion);
+                            }
+                            Some(additional) => self.check(value, additional, &key_path),
+                            None => {}
+                        }
+                    }
+                }
+            }
+            Node::Array(items) => {
+                if let Some(SingleOrVec::Single(item)) =
+                    schema.array.as_ref().and_then(|array| array.items.as_ref())
+                {
+                    for value in items {
+                        self.check(value, item, path);
+                    }
+                }
+            }
+            Node::Value => {}
+        }
+    }
+
+    /// Resolve references and pick the `anyOf` alternative (i.e. an `Option`)
+    /// matching the kind of `node`
+    fn resolve(&self, schema: &Schema, node: &Node) -> Option<SchemaObject> {
+        let Schema::Object(object) = schema else {
+            return None;
+        };
+        if let Some(reference) = &object.reference {
+            let name = reference.trim_start_matches("#/definitions/");
+            let schema = self.schema.definitions.get(name)?;
+            return self.resolve(schema, node);
+        }
+        if let Some(subschemas) = &object.subschemas {
+            let candidates = subschemas
+                .any_of
+                .iter()
+                .chain(subschemas.all_of.iter())
+                .flatten();
+            return candidates
+                .filter_map(|schema| self.resolve(schema, node))
+                .find(|schema| match node {
+                    Node::Table(_) => schema.has_type(InstanceType::Object),
+                    Node::Array(_) => schema.has_type(InstanceType::Array),
+                    Node::Value => true,
+                });
+        }
+        Some(object.clone())
+    }
+
+    fn unknown(&mut self, path: &str, span: Option<Range<usize>>, suggestion: Option<&String>) {
+        let mut warning = format!("Unknown key `{path}` in `{}`", self.file.display());
+        if let Some(span) = span {
+            let (line, column) = line_column(self.text, span.start);
+            warning += &format!(" at line {line}, column {column}");
+        }
+        if let Some(suggestion) = suggestion {
+            warning += &format!("; did you mean `{suggestion}`?");
+        }
+        self.warnings.push(warning);
+    }
+}
+
+/// Returns the known key closest to an unknown `key`
+fn suggest<'k, I>(key: &str, known: I) -> Option<&'k String>
+where
+    I: Iterator<Item = &'k String>,
+{
+    known
+        .map(|candidate| (strsim::jaro_winkler(key, candidate), candidate))
+        .filter(|(similarity, _)| *similarity > 0.8)
+        .max_by(|(a, _), (b, _)| a.total_cmp(b))
+        .map(|(_, candidate)| candidate)
+}
+
+/// 1-based line and column of a byte `offset` in `text`
+fn line_column(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset.min(text.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before
+        .rfind('\n')
+        .map(|newline| before[newline + 1..].chars().count())
+        .unwrap_or_else(|| before.chars().count())
+        + 1;
+    (line, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_datetimes_in_manifests() {
+        let text =
+            "published = 2024-01-01\n[site]\nupdated = 2024-01-01T10:00:00Z\ntime = 10:00:00";
+        let warnings = validate(Path::new("wahoo.toml"), text, FileKind::Manifest).unwrap();
+        assert!(warnings.is_empty(), "{warnings:?}");
+    }
+
+    #[test]
+    fn accepts_datetimes_in_sections() {
+        let text = "[settings]\nfolder = 'posts'\n[[posts]]\ndate = 2024-01-01";
+        let warnings = validate(Path::new("_section.toml"), text, FileKind::Section).unwrap();
+        assert!(warnings.is_empty(), "{warnings:?}");
+    }
+
+    #[test]
+    fn reports_unknown_keys_with_their_location() {
+        let text = "[settings]\npublished = 2024-01-01\nwatchh = []";
+        let warnings = validate(Path::new("wahoo.toml"), text, FileKind::Manifest).unwrap();
+        assert_eq!(
+            warnings,
+            [
+                "Unknown key `settings.published` in `wahoo.toml` at line 2, column 1",
+                "Unknown key `settings.watchh` in `wahoo.toml` at line 3, column 1; did you mean `watch`?"
+            ]
+        );
+    }
+}
@@ -1,7 +1,16 @@
+use crate::asset::Asset;
+use crate::dashboard;
 use crate::prelude::*;
+use crate::proxy::Proxy;
+use crate::rules::Rules;
+use crate::tls::Tls;
+use async_std::net::{SocketAddr, TcpListener, ToSocketAddrs};
 use async_std::prelude::*;
 use notify::RecursiveMode;
-use notify_debouncer_mini::new_debouncer;
+use notify_debouncer_mini::{
+    new_debouncer, DebounceEventResult, DebouncedEvent, DebouncedEventKind,
+};
+use std::sync::mpsc::Sender;
 use std::sync::Mutex;
 use std::{collections::HashMap, time::Duration};
 use tide_websockets::{Message, WebSocket};
@@ -9,11 +18,16 @@ use workflow_core::id::Id;
 // use std::hash::BuildHasher;
 use tide::http::mime;
 //use tide::utils::After;
+use tide::listener::ConcurrentListener;
 use tide::utils::async_trait;
 use tide::Middleware;
 use tide::{Response, Result, StatusCode};
 
 use serde::Serialize;
+
+/// Number of subsequent ports tried when the port is in use
+const PORT_ATTEMPTS: u16 = 10;
+
 // #[derive(Debug, Serialize)]
 // struct UpdateNotification {
 //     files: Vec<String>,
@@ -24,6 +38,149 @@ use serde::Serialize;
 //     id : String
 // }
 
+/// Settings that take effect after restarting `wahoo serve`
+struct RestartSettings {
+    /// prefix and `settings.languages` of each site
+    sites: Vec<(String, Option<Vec<String>>)>,
+    serve: ServeSettings,
+}
+
+impl RestartSettings {
+    fn new(sites: &[ServedSite], serve: ServeSettings) -> Self {
+        Self {
+            sites: sites
+                .iter()
+                .map(|site| (site.prefix.clone(), site.settings.languages.clone()))
+                .collect(),
+            serve,
+        }
+    }
+
+    /// Returns the names of the settings that differ from `other`
+    fn changes(&self, other: &RestartSettings) -> Vec<&'static str> {
+        let mut changes = vec![];
+        let prefixes = |settings: &RestartSettings| {
+            settings
+                .sites
+                .iter()
+                .map(|(prefix, _)| prefix.clone())
+                .collect::<Vec<_>>()
+        };
+        if prefixes(self) != prefixes(other) {
+            changes.push("`workspace.members`");
+        } else if self.sites != other.sites {
+            changes.push("`settings.languages`");
+        }
+        if self.serve.host != other.serve.host {
+            changes.push("`serve.host`");
+        }
+        if self.serve.port != other.serve.port {
+            changes.push("`serve.port`");
+        }
+        if self.serve.proxy != other.serve.proxy {
+            changes.push("`serve.proxy`");
+        }
+        if self.serve.headers != other.serve.headers {
+            changes.push("`serve.headers`");
+        }
+        if self.serve.redirects != other.serve.redirects {
+            changes.push("`serve.redirects`");
+        }
+        if self.serve.fallback != other.serve.fallback {
+            changes.push("`serve.fallback`");
+        }
+        changes
+    }
+}
+
+/// Live-reload client connected to the `/wahoo` websocket
+#[derive(Debug, Clone, Serialize)]
+pub struct ClientInfo {
+    pub id: String,
+    pub address: Option<String>,
+    pub user_agent: Option<String>,
+    /// connection time in milliseconds since the unix epoch
+    pub connected: u64,
+    /// page currently displayed by the client
+    pub url: Option<String>,
+    pub viewport: Option<Viewport>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Viewport {
+    pub width: u32,
+    pub height: u32,
+}
+
+/// `location` request params
+#[derive(Debug, Deserialize)]
+struct Location {
+    url: String,
+    viewport: Option<Viewport>,
+}
+
+/// `navigate` request params
+#[derive(Debug, Deserialize)]
+struct Navigate {
+    url: String,
+}
+
+/// Request sent by a client over the `/wahoo` websocket; requests
+/// without an `id` do not receive a response
+#[derive(Debug, Deserialize)]
+struct ClientRequest {
+    id: Option<String>,
+    method: String,
+    #[serde(default)]
+    params: serde_json::Value,
+}
+
+#[derive(Debug, Serialize)]
+struct ClientResponse {
+    id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// `build` notification params
+#[derive(Debug, Serialize)]
+struct BuildProgress {
+    stage: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    build: Option<BuildInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    errors: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    warnings: Option<usize>,
+}
+
+impl BuildProgress {
+    fn stage(stage: &str) -> Self {
+        Self {
+            stage: stage.to_string(),
+            build: None,
+            errors: None,
+            warnings: None,
+        }
+    }
+
+    fn finished(server: &Server) -> Self {
+        Self {
+            stage: "finished".to_string(),
+            build: server.build_info(),
+            errors: Some(server.errors().len()),
+            warnings: Some(server.warnings().len()),
+        }
+    }
+}
+
+struct Client {
+    connection: Arc<tide_websockets::WebSocketConnection>,
+    info: ClientInfo,
+}
+
 #[derive(Debug, Serialize)]
 struct Notification<Msg>
 where
@@ -34,6 +191,17 @@ where
     params: Msg,
 }
 
+/// Same-origin path (`/docs/?page=2`); scheme-relative urls (`//host`),
+/// backslashes and control characters, which browsers may turn into
+/// one, are rejected
+fn is_local_path(url: &str) -> bool {
+    url.starts_with('/')
+        && !url.starts_with("//")
+        && !url
+            .chars()
+            .any(|c| c == '\\' || c.is_control() || c.is_whitespace())
+}
+
 fn notification<P>(method: &str, params: P) -> String
 where
     P: Serialize,
@@ -47,21 +215,83 @@ where
     serde_json::to_string(&notification).unwrap()
 }
 
+/// A site served from memory under its workspace member prefix
+pub struct ServedSite {
+    /// path prefix without leading and trailing slashes (empty for the root)
+    prefix: String,
+    src_folder: PathBuf,
+    site_folder: PathBuf,
+    settings: Settings,
+    sink: Sink,
+}
+
+impl ServedSite {
+    pub fn new(site: &Site, sink: Sink) -> Self {
+        Self {
+            prefix: site.prefix.clone(),
+            src_folder: site.ctx.src_folder.clone(),
+            site_folder: site.ctx.site_folder.clone(),
+            settings: site.ctx.settings(),
+            sink,
+        }
+    }
+
+    /// Site url of a path relative to the site root (starting with `/`)
+    fn url(&self, path: &str) -> String {
+        if self.prefix.is_empty() {
+            path.to_string()
+        } else {
+            format!("/{}{path}", self.prefix)
+        }
+    }
+
+    /// Locale served from the site root when `settings.languages` are used
+    fn root_locale(&self) -> Option<&str> {
+        let languages = self.settings.languages.as_ref()?;
+        if languages.contains(&"en".to_string()) {
+            Some("en")
+        } else {
+            languages.first().map(|locale| locale.as_str())
+        }
+    }
+
+    /// Locale folder of a path relative to the site root
+    fn locale_folder(&self, path: &str) -> PathBuf {
+        let locale = path.trim_start_matches('/').split('/').next().unwrap();
+        match &self.settings.languages {
+            Some(languages) if languages.iter().any(|language| language == locale) => {
+                PathBuf::from(locale)
+            }
+            _ => PathBuf::new(),
+        }
+    }
+}
+
 pub struct Server {
     // ctx : Arc<Context>,
     // pub tide : tide::Server<()>,
     // verbose: bool,
-    host: String,
+    hosts: Vec<String>,
     port: u16,
+    /// addresses the http server is bound to
+    addresses: Mutex<Vec<SocketAddr>>,
     location: Option<String>,
+    /// build options used when rebuilding, including `-D` overrides
+    options: Options,
     project_folder: PathBuf,
-    site_folder: PathBuf,
-    watch_targets: Vec<PathBuf>,
-    settings: Settings,
-    websockets: Arc<Mutex<HashMap<Id, Arc<tide_websockets::WebSocketConnection>>>>,
+    watch_targets: Mutex<Vec<PathBuf>>,
+    proxies: Vec<Proxy>,
+    rules: Rules,
+    /// manifest settings that require a restart, as last seen
+    restart_settings: Mutex<RestartSettings>,
+    websockets: Arc<Mutex<HashMap<Id, Client>>>,
+    /// sender of the file watcher channel, used to trigger rebuilds
+    rebuild: Mutex<Option<Sender<DebounceEventResult>>>,
     session: Id,
     hashes: Mutex<HashMap<String, u64>>,
-    sink: Sink,
+    /// served sites, ordered by prefix length
+    sites: Vec<ServedSite>,
+    tls: Option<Tls>,
     // verbose: bool,
 }
 
@@ -69,44 +299,85 @@ impl Server {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
         // ctx : &Arc<Context>,
-        host: String,
+        hosts: Vec<String>,
         port: u16,
         location: Option<String>,
+        options: Options,
         project_folder: PathBuf,
-        site_folder: PathBuf,
         watch_targets: &[PathBuf],
-        settings: Settings,
-        sink: Sink,
+        serve: ServeSettings,
+        sites: Vec<ServedSite>,
+        tls: Option<Tls>,
         // verbose : bool,
-    ) -> Arc<Server> {
+    ) -> Result<Arc<Server>> {
+        let proxies = Proxy::from_settings(&serve)?;
+        let rules = Rules::try_new(&serve)?;
+        let websockets: Arc<Mutex<HashMap<Id, Client>>> = Arc::new(Mutex::new(HashMap::new()));
+
+        // build stages are reported as they happen, while the build is running;
+        // messages are sent by a separate task so that the build never waits
+        // on the websocket connections
+        let (progress, mut messages) = futures::channel::mpsc::unbounded::<String>();
+        let clients = websockets.clone();
+        tokio::spawn(async move {
+            while let Some(msg) = messages.next().await {
+                let connections = clients
+                    .lock()
+                    .unwrap()
+                    .values()
+                    .map(|client| client.connection.clone())
+                    .collect::<Vec<_>>();
+                for connection in connections {
+                    connection.send(Message::Text(msg.clone())).await.ok();
+                }
+            }
+        });
+        for site in sites.iter() {
+            let progress = progress.clone();
+            site.sink.on_progress(Arc::new(move |stage: &str| {
+                progress
+                    .unbounded_send(notification("build", BuildProgress::stage(stage)))
+                    .ok();
+            }));
+        }
+
         let server = Self {
             // ctx : ctx.clone(),
-            host,
+            hosts,
             port,
+            addresses: Mutex::new(Vec::new()),
             location,
+            options,
+            restart_settings: Mutex::new(RestartSettings::new(&sites, serve)),
             project_folder,
-            site_folder,
-            watch_targets: watch_targets.to_vec(),
-            websockets: Arc::new(Mutex::new(HashMap::new())),
-            settings,
+            watch_targets: Mutex::new(watch_targets.to_vec()),
+            websockets,
+            rebuild: Mutex::new(None),
+            proxies,
+            rules,
             session: Id::new(),
             hashes: Mutex::new(HashMap::new()),
-            sink,
+            sites,
+            tls,
             // verbose
         };
 
-        Arc::new(server)
+        Ok(Arc::new(server))
     }
 
-    pub async fn run(self: Arc<Self>) -> Result<()> {
+    pub async fn run(self: Arc<Self>) -> crate::result::Result<()> {
+        // bind before watching, so that listen errors are reported to the caller
+        let listeners = self.bind().await?;
+
         // setup debouncer
         let (tx, rx) = std::sync::mpsc::channel();
+        *self.rebuild.lock().unwrap() = Some(tx.clone());
 
         // No specific tickrate, max debounce time 2 seconds
         let mut debouncer = new_debouncer(Duration::from_millis(500), None, tx).unwrap();
 
         let watcher = debouncer.watcher();
-        for path in self.watch_targets.iter() {
+        for path in self.watch_targets.lock().unwrap().iter() {
             log_trace!("Watching", "{}", style(path.to_str().unwrap()).cyan());
             watcher.watch(Path::new(&path), RecursiveMode::Recursive)?;
         }
@@ -117,7 +388,7 @@ impl Server {
 
         let this = self.clone();
         tokio::spawn(async move {
-            match this.http_server().await {
+            match this.http_server(listeners).await {
                 Ok(_) => {}
                 Err(e) => {
                     log_error!("Server Error: {:?}", e);
@@ -125,7 +396,7 @@ impl Server {
             }
         });
 
-        for (_index, events) in rx.iter().enumerate() {
+        for events in rx.iter() {
             // log_info!("", "");
             if events.is_err() {
                 continue;
@@ -137,16 +408,31 @@ impl Server {
             // let build = Arc::new(Builder::new(ctx));
             // build.execute().await?;
 
-            let files: Vec<String> = events
+            // stylesheets migrated from the source folder are hot-swapped
+            // by the client without reloading the page
+            let mut stylesheets = Vec::new();
+            let mut stylesheet_files = Vec::new();
+            let files: Vec<PathBuf> = events
                 .unwrap()
                 .iter()
                 .filter_map(|event| {
                     let f = event
                         .path
                         .strip_prefix(&self.project_folder)
-                        .expect("watched file is not in the project folder");
+                        .unwrap_or(&event.path);
                     let file_str = f.as_os_str().to_str().unwrap().to_string();
 
+                    if !event.path.exists() {
+                        // deleted or renamed (the new name arrives as a separate event)
+                        log_info!("Removed", "`{}`", file_str);
+                        self.hashes.lock().unwrap().remove(&file_str);
+                        return Some(event.path.clone());
+                    }
+
+                    if event.path.is_dir() {
+                        return Some(event.path.clone());
+                    }
+
                     match std::fs::read(&event.path) {
                         Ok(content) => {
                             let hash = make_hash(&content);
@@ -159,17 +445,13 @@ impl Server {
                                 }
                             }
 
-                            if file_str.contains("templates/") {
-                                let parts = file_str.split("templates/").collect::<Vec<_>>();
-                                if parts.len() == 2 {
-                                    // parts.next();
-                                    parts.last().map(|v| v.to_string())
-                                } else {
-                                    None
-                                }
-                            } else {
-                                Some(file_str)
+                            if let Some(stylesheet) = self.stylesheet(&event.path) {
+                                stylesheets.push(stylesheet);
+                                stylesheet_files.push(event.path.clone());
+                                return None;
                             }
+
+                            Some(event.path.clone())
                         }
                         Err(err) => {
                             log_error!("Unable to read `{}`: {}", event.path.display(), err);
@@ -179,44 +461,297 @@ impl Server {
                 })
                 .collect();
 
-            if !files.is_empty() {
-                let ctx = Arc::new(
-                    Context::create(
-                        self.location.clone(),
-                        Options {
-                            server: true,
-                            ..Options::default()
-                        },
-                    )
-                    .await?,
-                );
-                let site_folder = ctx.site_folder.clone();
-                let build = Arc::new(Builder::new_with_sink(ctx, self.sink.clone()));
-                build.execute().await?;
+            if !files.is_empty() || !stylesheets.is_empty() {
+                let options = Options {
+                    verbose: false,
+                    ..self.options.clone()
+                };
+                let project = match Project::load(self.location.clone(), &options).await {
+                    Ok(project) => project,
+                    Err(err) => {
+                        log_error!("{err}");
+                        self.post_errors(vec![BuildError::new(&err.to_string())])
+                            .await?;
+                        continue;
+                    }
+                };
+                self.refresh(&project, debouncer.watcher());
+
+                // only the members whose inputs changed are rebuilt, unless
+                // a file is not an input of any member (i.e. the workspace manifest)
+                let mut changed = AHashSet::new();
+                let mut rebuild_all = false;
+                for file in files.iter().chain(stylesheet_files.iter()) {
+                    let sites = project.sites_of(file);
+                    rebuild_all |= sites.is_empty();
+                    changed.extend(sites.into_iter().map(|site| site.prefix.clone()));
+                }
 
-                // let noti = UpdateNotification { files };
-                // let str = serde_json::to_string(&noti)?;
-                let update = notification("update", files);
-                // log_info!("Notification", "{}", update);
-                self.post(&update).await?;
+                self.post(&notification("build", BuildProgress::stage("started")))
+                    .await?;
+                for site in project.sites.iter() {
+                    if !rebuild_all && !changed.contains(&site.prefix) {
+                        continue;
+                    }
+                    // members added to the workspace are served after a restart
+                    let Some(served) = self.site(&site.prefix) else {
+                        continue;
+                    };
+                    let build = Arc::new(Builder::new_with_sink(
+                        site.ctx.clone(),
+                        served.sink.clone(),
+                    ));
+                    if let Err(err) = build.execute().await {
+                        log_error!("{err}");
+                        served.sink.error(BuildError::new(&err.to_string()));
+                    }
+                }
+                self.post(&notification("build", BuildProgress::finished(&self)))
+                    .await?;
 
-                let update_json_file = site_folder.join("__wahoo.json");
-                std::fs::write(update_json_file, update).ok();
+                // an empty error list clears the error overlay in the browser
+                self.post_errors(self.errors()).await?;
 
-                log_trace!("HTTP", "server listening on port {}", self.port);
+                if !stylesheets.is_empty() {
+                    self.post(&notification("css", stylesheets)).await?;
+                }
+
+                let urls = self.affected(&project, &files);
+                if !urls.is_empty() {
+                    // let noti = UpdateNotification { files };
+                    // let str = serde_json::to_string(&noti)?;
+                    let update = notification("update", urls);
+                    // log_info!("Notification", "{}", update);
+                    self.post(&update).await?;
+                }
+
+                let addresses = self
+                    .addresses()
+                    .iter()
+                    .map(|address| address.to_string())
+                    .collect::<Vec<_>>();
+                log_trace!("HTTP", "server listening on {}", addresses.join(", "));
                 // log_info!("Server", "monitoring changes...",);
             }
         }
         Ok(())
     }
 
+    /// Update watched paths and report settings requiring a restart
+    /// after the manifest has been reloaded
+    fn refresh(&self, project: &Project, watcher: &mut dyn notify::Watcher) {
+        let targets = project.watch_targets();
+        let mut watch_targets = self.watch_targets.lock().unwrap();
+
+        for path in watch_targets.iter() {
+            if !targets.contains(path) {
+                log_info!(
+                    "Watching",
+                    "{} `{}`",
+                    style("remove:").yellow(),
+                    path.display()
+                );
+                watcher.unwatch(path).unwrap_or_else(|err| {
+                    log_warn!("Watch", "Unable to unwatch `{}`: {err}", path.display());
+                });
+            }
+        }
+
+        for path in targets.iter() {
+            if !watch_targets.contains(path) {
+                log_info!("Watching", "{} `{}`", style("add:").cyan(), path.display());
+                watcher
+                    .watch(path, RecursiveMode::Recursive)
+                    .unwrap_or_else(|err| {
+                        log_warn!("Watch", "Unable to watch `{}`: {err}", path.display());
+                    });
+            }
+        }
+
+        *watch_targets = targets;
+
+        let settings = RestartSettings {
+            sites: project
+                .sites
+                .iter()
+                .map(|site| (site.prefix.clone(), site.ctx.settings().languages))
+                .collect(),
+            serve: project.serve.clone().unwrap_or_default(),
+        };
+        let mut restart_settings = self.restart_settings.lock().unwrap();
+        let changes = restart_settings.changes(&settings);
+        if !changes.is_empty() {
+            log_warn!(
+                "Restart",
+                "changes to {} take effect after restarting `wahoo serve`",
+                changes.join(", ")
+            );
+            *restart_settings = settings;
+        }
+    }
+
+    /// Returns urls of the rendered pages affected by the changed files
+    fn affected(&self, project: &Project, files: &[PathBuf]) -> Vec<String> {
+        let mut urls = vec![];
+        for site in project.sites.iter() {
+            let Some(served) = self.site(&site.prefix) else {
+                continue;
+            };
+
+            // only files watched for a site affect its pages
+            let targets = site.ctx.watch_targets();
+            let files = files
+                .iter()
+                .filter(|file| targets.iter().any(|target| file.starts_with(target)))
+                .cloned()
+                .collect::<Vec<_>>();
+            if files.is_empty() {
+                continue;
+            }
+
+            let affected = served.sink.affected(&files);
+            // the root locale index is also served as the site root
+            if let Some(root_locale) = served.root_locale() {
+                if affected.contains(&format!("/{root_locale}/index.html")) {
+                    urls.push(served.url("/index.html"));
+                }
+            }
+            urls.extend(affected.iter().map(|url| served.url(url)));
+        }
+        urls
+    }
+
+    /// Served site of a workspace member `prefix`
+    fn site(&self, prefix: &str) -> Option<&ServedSite> {
+        self.sites.iter().find(|site| site.prefix == prefix)
+    }
+
+    /// Returns the served site of a request `path` (the site with the longest
+    /// matching prefix) along with the path relative to the site root
+    fn site_of<'p>(&self, path: &'p str) -> Option<(&ServedSite, &'p str)> {
+        self.sites.iter().rev().find_map(|site| {
+            if site.prefix.is_empty() {
+                return Some((site, path));
+            }
+            let relative = path
+                .strip_prefix('/')
+                .and_then(|path| path.strip_prefix(&site.prefix))?;
+            (relative.is_empty() || relative.starts_with('/')).then_some((site, relative))
+        })
+    }
+
+    /// Returns the url path of a stylesheet migrated as-is
+    /// from the source folder of a site (without leading `/`)
+    fn stylesheet(&self, path: &Path) -> Option<String> {
+        if path.extension().and_then(|ext| ext.to_str()) != Some("css") {
+            return None;
+        }
+
+        self.sites.iter().rev().find_map(|site| {
+            let relative = path.strip_prefix(&site.src_folder).ok()?;
+            let url = site.url(&format!("/{}", relative.to_str()?.replace('\\', "/")));
+            Some(url.trim_start_matches('/').to_string())
+        })
+    }
+
+    fn is_proxied(&self, path: &str) -> bool {
+        self.proxies.iter().any(|proxy| proxy.is_match(path))
+    }
+
+    /// Map a request path to a served site and a file relative to its site folder
+    fn resolve(&self, path: &str) -> Option<(&ServedSite, PathBuf)> {
+        let (site, path) = self.site_of(path)?;
+        let mut file = PathBuf::new();
+        for component in Path::new(path).components() {
+            match component {
+                Component::Normal(part) => file.push(part),
+                Component::ParentDir => {
+                    file.pop();
+                }
+                _ => {}
+            }
+        }
+
+        if file.as_os_str().is_empty() {
+            if let Some(root_locale) = site.root_locale() {
+                return Some((site, Path::new(root_locale).join("index.html")));
+            }
+        }
+
+        let file = if path.ends_with('/')
+            || file.as_os_str().is_empty()
+            || (file.extension().is_none() && site.sink.get(&file.join("index.html")).is_some())
+        {
+            file.join("index.html")
+        } else {
+            file
+        };
+        Some((site, file))
+    }
+
+    /// SPA fallback page for unmatched routes requested by the browser
+    fn fallback(&self, request: &tide::Request<()>) -> Option<(PathBuf, Entry)> {
+        let fallback = self.rules.fallback()?;
+        let accepts_html = request
+            .header("accept")
+            .map(|accept| accept.as_str().contains("text/html"))
+            .unwrap_or(false);
+        if accepts_html {
+            let (site, file) = self.resolve(fallback)?;
+            site.sink.get(&file).map(|entry| (file, entry))
+        } else {
+            None
+        }
+    }
+
+    /// Serve the site from the in-memory output store
+    async fn serve(&self, request: tide::Request<()>) -> tide::Result {
+        let Some((site, file)) = self.resolve(request.url().path()) else {
+            return Ok(Response::new(StatusCode::NotFound));
+        };
+
+        let asset = match site.sink.get(&file) {
+            Some(entry) => Asset::try_new(&file, entry).await,
+            None if file.starts_with("node_modules") => {
+                let entry = Entry::File(site.site_folder.join(&file));
+                Asset::try_new(&file, entry).await
+            }
+            None => match self.fallback(&request) {
+                Some((file, entry)) => Asset::try_new(&file, entry).await.map(Asset::html),
+                None => return Ok(Response::new(StatusCode::NotFound)),
+            },
+        };
+
+        // entries removed since the lookup, or folders, are not found
+        match asset {
+            Ok(asset) => asset.respond(&request).await,
+            Err(_) => Ok(Response::new(StatusCode::NotFound)),
+        }
+    }
+
+    /// Read a file relative to the site folder from the in-memory output store
+    async fn read(site: &ServedSite, file: &Path) -> tide::Result<String> {
+        match site.sink.get(file) {
+            Some(Entry::Content(content, _)) => Ok(String::from_utf8_lossy(&content).to_string()),
+            Some(Entry::File(path)) => Ok(fs::read_to_string(path).await?),
+            None => Err(tide::Error::from_str(
+                StatusCode::NotFound,
+                format!("Unable to locate `{}`", file.display()),
+            )),
+        }
+    }
+
+    async fn post_errors(&self, errors: Vec<BuildError>) -> Result<()> {
+        self.post(&notification("errors", errors)).await
+    }
+
     async fn post(&self, msg: &str) -> Result<()> {
         let websockets = self
             .websockets
             .lock()
             .unwrap()
             .values()
-            .cloned()
+            .map(|client| client.connection.clone())
             .collect::<Vec<_>>();
 
         for websocket in websockets {
@@ -226,74 +761,281 @@ impl Server {
         Ok(())
     }
 
+    /// Navigate all connected clients (except `origin`) to `url`,
+    /// which must be a path on this server
+    pub async fn navigate(&self, url: &str, origin: Option<Id>) -> crate::result::Result<usize> {
+        if !is_local_path(url) {
+            return Err(format!("unable to navigate to `{url}`: not a path on this server").into());
+        }
+
+        let websockets = self
+            .websockets
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(id, _)| Some(**id) != origin)
+            .map(|(_, client)| client.connection.clone())
+            .collect::<Vec<_>>();
+
+        let msg = notification("navigate", url);
+        for websocket in websockets.iter() {
+            websocket.send(Message::Text(msg.clone())).await.ok();
+        }
+
+        Ok(websockets.len())
+    }
+
+    /// Handle a request received from client `id`, returning the response
+    async fn handle(&self, id: Id, text: &str) -> Option<String> {
+        let request = match serde_json::from_str::<ClientRequest>(text) {
+            Ok(request) => request,
+            Err(err) => {
+                log_warn!("Client", "invalid request `{text}`: {err}");
+                return None;
+            }
+        };
+
+        let result = match request.method.as_str() {
+            "location" => serde_json::from_value::<Location>(request.params)
+                .map_err(|err| err.to_string())
+                .map(|location| {
+                    if let Some(client) = self.websockets.lock().unwrap().get_mut(&id) {
+                        client.info.url = Some(location.url);
+                        client.info.viewport = location.viewport;
+                    }
+                    serde_json::Value::Null
+                }),
+            "rebuild" => {
+                log_info!("Client", "rebuild requested");
+                Ok(serde_json::Value::Bool(self.rebuild()))
+            }
+            "navigate" => match serde_json::from_value::<Navigate>(request.params) {
+                Ok(navigate) => self
+                    .navigate(&navigate.url, Some(id))
+                    .await
+                    .map(serde_json::Value::from)
+                    .map_err(|err| err.to_string()),
+                Err(err) => Err(err.to_string()),
+            },
+            method => Err(format!("unknown method `{method}`")),
+        };
+
+        let id = request.id?;
+        let response = match result {
+            Ok(result) => ClientResponse {
+                id,
+                result: Some(result),
+                error: None,
+            },
+            Err(error) => ClientResponse {
+                id,
+                result: None,
+                error: Some(error),
+            },
+        };
+        serde_json::to_string(&response).ok()
+    }
+
     // fn target_folder(&self, path: &str) -> PathBuf {
     //     self.target_folder.join(path)
     // }
 
-    async fn http_server(self: Arc<Self>) -> Result<()> {
-        let mut app = tide::new();
-        app.with(tide::log::LogMiddleware::new());
-        app.at("/")
-            .serve_dir(&self.site_folder)
-            .map_err(|err| -> Error {
-                format!(
-                    "Unable to locate target folder `{}`: {err}",
-                    self.site_folder.display()
-                )
-                .into()
-            })?;
-
-        let empty_list = vec![];
-        let languages = self.settings.languages.as_ref().unwrap_or(&empty_list);
-        if languages.is_empty() {
-            let target = self.site_folder.join("index.html");
-            app.at("/").serve_file(&target).map_err(|err| -> Error {
-                format!(
-                    "Unable to locate target folder `{}`: {err}",
-                    target.display()
-                )
-                .into()
-            })?;
-        } else {
-            let root_locale = if languages.contains(&"en".to_string()) {
-                "en"
-            } else {
-                languages[0].as_str()
-            };
+    /// Bind all hosts on the same port; if the port is in use the subsequent
+    /// ports are tried, and port `0` binds to a port assigned by the system
+    async fn bind(&self) -> crate::result::Result<Vec<TcpListener>> {
+        if self.hosts.is_empty() {
+            return Err("no hosts to listen on (`serve.host` is empty)".into());
+        }
+
+        let mut port = self.port;
+        let mut attempts = 0;
+        let listeners = loop {
+            match self.bind_port(port).await {
+                Ok(listeners) => break listeners,
+                Err(err)
+                    if err.kind() == std::io::ErrorKind::AddrInUse
+                        && port != 0
+                        && port < u16::MAX
+                        && attempts < PORT_ATTEMPTS =>
+                {
+                    log_warn!("HTTP", "port {port} is in use, trying {}", port + 1);
+                    port += 1;
+                    attempts += 1;
+                }
+                Err(err) => {
+                    return Err(format!(
+                        "failed to listen on {} (port {port}): {err}",
+                        self.hosts.join(", ")
+                    )
+                    .into())
+                }
+            }
+        };
 
-            let target = self.site_folder.join(format!("{root_locale}/index.html"));
-            app.at("/").serve_file(&target).map_err(|err| -> Error {
-                format!("Unable to locate `{}`: {err}", target.display()).into()
-            })?;
-
-            for locale in languages {
-                let target = self.site_folder.join(format!("{locale}/index.html"));
-                for path in [
-                    format!("/{locale}").as_str(),
-                    format!("/{locale}/").as_str(),
-                ] {
-                    app.at(path).serve_file(&target).map_err(|err| -> Error {
-                        format!("Unable to locate `{}`: {err}", target.display()).into()
-                    })?;
+        let mut addresses = Vec::new();
+        for listener in listeners.iter() {
+            addresses.push(listener.local_addr()?);
+        }
+        *self.addresses.lock().unwrap() = addresses;
+
+        let scheme = if self.tls.is_some() { "https" } else { "http" };
+        for address in self.addresses.lock().unwrap().iter() {
+            log_info!("HTTP", "server listening on {scheme}://{address}");
+        }
+
+        Ok(listeners)
+    }
+
+    async fn bind_port(&self, mut port: u16) -> std::io::Result<Vec<TcpListener>> {
+        let mut listeners = Vec::new();
+        let mut error = None;
+        for host in self.hosts.iter() {
+            let host = host.trim_start_matches('[').trim_end_matches(']');
+            for address in (host, port).to_socket_addrs().await? {
+                // with port `0`, all addresses share the port assigned to the first
+                let address = SocketAddr::new(address.ip(), port);
+                match TcpListener::bind(address).await {
+                    Ok(listener) => {
+                        port = listener.local_addr()?.port();
+                        listeners.push(listener);
+                    }
+                    Err(err) if err.kind() == std::io::ErrorKind::AddrInUse => return Err(err),
+                    Err(err) => {
+                        // i.e. IPv6 being unavailable for `localhost`
+                        log_warn!("HTTP", "unable to listen on {address}: {err}");
+                        error = Some(err);
+                    }
                 }
             }
         }
 
+        match error {
+            Some(err) if listeners.is_empty() => Err(err),
+            _ => Ok(listeners),
+        }
+    }
+
+    /// Addresses the http server is bound to
+    pub fn addresses(&self) -> Vec<SocketAddr> {
+        self.addresses.lock().unwrap().clone()
+    }
+
+    /// Build errors of all served sites
+    pub fn errors(&self) -> Vec<BuildError> {
+        self.sites
+            .iter()
+            .flat_map(|site| site.sink.errors())
+            .collect()
+    }
+
+    /// Build warnings of all served sites
+    pub fn warnings(&self) -> Vec<String> {
+        self.sites
+            .iter()
+            .flat_map(|site| site.sink.warnings())
+            .collect()
+    }
+
+    /// Latest build of the served sites
+    pub fn build_info(&self) -> Option<BuildInfo> {
+        self.sites
+            .iter()
+            .filter_map(|site| site.sink.build_info())
+            .max_by_key(|build| build.finished)
+    }
+
+    /// Rendered pages of all served sites, with their sources
+    pub fn pages(&self) -> Vec<(String, Vec<PathBuf>)> {
+        self.sites
+            .iter()
+            .flat_map(|site| {
+                site.sink
+                    .pages()
+                    .into_iter()
+                    .map(|(url, sources)| (site.url(&url), sources))
+            })
+            .collect()
+    }
+
+    pub fn project_folder(&self) -> &Path {
+        &self.project_folder
+    }
+
+    /// Connected live-reload clients
+    pub fn clients(&self) -> Vec<ClientInfo> {
+        let mut clients = self
+            .websockets
+            .lock()
+            .unwrap()
+            .values()
+            .map(|client| client.info.clone())
+            .collect::<Vec<_>>();
+        clients.sort_by_key(|client| client.connected);
+        clients
+    }
+
+    /// Trigger a full rebuild via the file watcher loop
+    pub fn rebuild(&self) -> bool {
+        // the project folder is not known to any page, affecting all pages
+        let event = DebouncedEvent {
+            path: self.project_folder.clone(),
+            kind: DebouncedEventKind::Any,
+        };
+        match self.rebuild.lock().unwrap().as_ref() {
+            Some(sender) => sender.send(Ok(vec![event])).is_ok(),
+            None => false,
+        }
+    }
+
+    async fn http_server(self: Arc<Self>, listeners: Vec<TcpListener>) -> Result<()> {
+        let mut app = tide::new();
+        for proxy in self.proxies.iter() {
+            log_info!("Proxy", "`{}` => `{}`", proxy.prefix, proxy.target());
+            proxy.clone().register(&mut app);
+        }
+
+        dashboard::register(self.clone(), &mut app);
+
+        for path in ["/", "/*"] {
+            let this = self.clone();
+            app.at(path).get(move |request: tide::Request<()>| {
+                let this = this.clone();
+                async move { this.serve(request).await }
+            });
+        }
+
         let this = self.clone();
         let websockets = this.websockets.clone();
 
-        app.at("/wahoo")
-            .get(WebSocket::new(move |_request, mut stream| {
+        app.at("/wahoo").get(WebSocket::new(
+            move |request: tide::Request<()>, mut stream| {
                 let websockets = websockets.clone();
                 let session = this.session;
+                let errors = this.errors();
+                let this = this.clone();
                 async move {
                     let id = Id::new();
+                    let info = ClientInfo {
+                        id: id.to_string(),
+                        address: request.remote().map(String::from),
+                        user_agent: request
+                            .header("user-agent")
+                            .map(|ua| ua.as_str().to_string()),
+                        connected: SystemTime::now()
+                            .duration_since(std::time::UNIX_EPOCH)
+                            .map(|duration| duration.as_millis() as u64)
+                            .unwrap_or_default(),
+                        url: None,
+                        viewport: None,
+                    };
 
-                    websockets
-                        .clone()
-                        .lock()
-                        .unwrap()
-                        .insert(id, Arc::new(stream.clone()));
+                    websockets.clone().lock().unwrap().insert(
+                        id,
+                        Client {
+                            connection: Arc::new(stream.clone()),
+                            info,
+                        },
+                    );
 
                     // let session = Session { id : session.to_string() };
                     stream
@@ -301,18 +1043,31 @@ impl Server {
                         .await
                         .ok();
 
-                    while let Some(Ok(Message::Text(_input))) = stream.next().await {
-                        // let output: String = input.chars().rev().collect();
-                        // stream
-                        //     .send_string(format!("{} | {}", &input, &output))
-                        //     .await?;
+                    if !errors.is_empty() {
+                        stream
+                            .send(Message::Text(notification("errors", errors)))
+                            .await
+                            .ok();
+                    }
+
+                    while let Some(Ok(message)) = stream.next().await {
+                        match message {
+                            Message::Text(text) => {
+                                if let Some(response) = this.handle(id, &text).await {
+                                    stream.send(Message::Text(response)).await.ok();
+                                }
+                            }
+                            Message::Close(_) => break,
+                            _ => {}
+                        }
                     }
 
                     websockets.clone().lock().unwrap().remove(&id);
 
                     Ok(())
                 }
-            }));
+            },
+        ));
 
         const NOT_FOUND_HTML_PAGE: &str = "<html>
             <body style=\"text-align:center;margin: 100px;\">
@@ -330,22 +1085,24 @@ impl Server {
           </body></html>";
 
         pub struct ErrorHandler {
-            site_folder: PathBuf,
             server: Arc<Server>,
-            languages: Vec<String>,
         }
 
         impl ErrorHandler {
-            async fn run(&self, response: Response, site_folder: PathBuf) -> tide::Result {
+            async fn run(
+                &self,
+                response: Response,
+                site: &ServedSite,
+                folder: PathBuf,
+            ) -> tide::Result {
                 let mut page_404 = NOT_FOUND_HTML_PAGE.to_string();
                 let mut page_500 = INTERNAL_SERVER_ERROR_HTML_PAGE.to_string();
 
                 let response = match response.status() {
                     StatusCode::NotFound => {
-                        if let Some(error_content) = &self.server.settings.error_404 {
+                        if let Some(error_content) = &site.settings.error_404 {
                             if error_content.ends_with(".html") {
-                                let path = site_folder.join(error_content);
-                                page_404 = fs::read_to_string(path).await?;
+                                page_404 = Server::read(site, &folder.join(error_content)).await?;
                             } else {
                                 page_404 = error_content.clone();
                             }
@@ -357,10 +1114,9 @@ impl Server {
                     }
 
                     StatusCode::InternalServerError => {
-                        if let Some(error_content) = &self.server.settings.error_500 {
+                        if let Some(error_content) = &site.settings.error_500 {
                             if error_content.ends_with(".html") {
-                                let path = site_folder.join(error_content);
-                                page_500 = fs::read_to_string(path).await?;
+                                page_500 = Server::read(site, &folder.join(error_content)).await?;
                             } else {
                                 page_500 = error_content.clone();
                             }
@@ -389,36 +1145,73 @@ impl Server {
                 next: tide::Next<'_, State>,
             ) -> tide::Result {
                 let url = request.url();
-                let site_folder = if let Some(mut path_segments) = url.path_segments() {
-                    let locale = path_segments.next().unwrap().to_string();
-                    if self.languages.contains(&locale) {
-                        self.site_folder.join(locale)
-                    } else {
-                        self.site_folder.clone()
-                    }
-                } else {
-                    self.site_folder.clone()
-                };
+                if self.server.is_proxied(url.path()) {
+                    return Ok(next.run(request).await);
+                }
+
+                let path = url.path().to_string();
                 let response = next.run(request).await;
-                self.run(response, site_folder).await
+                match self.server.site_of(&path) {
+                    Some((site, path)) => {
+                        let folder = site.locale_folder(path);
+                        self.run(response, site, folder).await
+                    }
+                    None => Ok(response),
+                }
             }
         }
 
         app.with(ErrorHandler {
-            site_folder: self.site_folder.clone(),
             server: self.clone(),
-            languages: languages.clone(),
         });
 
-        let address = format!("{}:{}", self.host, self.port);
-        log_info!("HTTP", "server listening on {address}");
-        log_info!("HTTP", "serving `{}`", self.site_folder.display());
-        if let Err(err) = app.listen(&address).await {
-            log_error!("failed to listen on {address}: {err}");
-            println!();
-            panic!("failed to listen on {address}");
+        log_info!("HTTP", "serving site from memory");
+        if let Some(address) = self.addresses().first() {
+            let scheme = if self.tls.is_some() { "https" } else { "http" };
+            log_info!("HTTP", "dashboard at {scheme}://{address}/__wahoo/");
+        }
+        // rules are applied before the routes are selected, so that
+        // rewrites are served by the route matching the rewritten url
+        let mut server = tide::new();
+        server.with(tide::log::LogMiddleware::new());
+        if !self.rules.is_empty() {
+            server.with(self.rules.clone());
+        }
+        server.at("/").all(app.clone());
+        server.at("/*").all(app);
+
+        let mut listener = ConcurrentListener::new();
+        for tcp_listener in listeners {
+            match &self.tls {
+                Some(tls) => listener.add(tls.listener(tcp_listener)?)?,
+                None => listener.add(tcp_listener)?,
+            }
         }
+        server.listen(listener).await?;
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn navigation_is_limited_to_local_paths() {
+        for url in ["/", "/docs/", "/search?q=a#top"] {
+            assert!(is_local_path(url), "{url}");
+        }
+        for url in [
+            "",
+            "docs",
+            "//example.com",
+            "/\\example.com",
+            "/\t/example.com",
+            "https://example.com/",
+            "javascript:alert(1)",
+        ] {
+            assert!(!is_local_path(url), "{url}");
+        }
+    }
+}
@@ -1,13 +1,19 @@
 use crate::prelude::*;
+use std::time::{Instant, UNIX_EPOCH};
 
 pub struct MigrateFileInfo {
+    source: PathBuf,
     modified: SystemTime,
     scan: u64,
 }
 
 impl MigrateFileInfo {
-    pub fn new(modified: SystemTime, scan: u64) -> Self {
-        Self { modified, scan }
+    pub fn new(source: PathBuf, modified: SystemTime, scan: u64) -> Self {
+        Self {
+            source,
+            modified,
+            scan,
+        }
     }
 }
 
@@ -39,14 +45,55 @@ impl Updates {
     }
 }
 
+/// Content held by the in-memory output store
+#[derive(Clone)]
+pub enum Entry {
+    /// Rendered content and the time it was last changed
+    Content(Arc<Vec<u8>>, SystemTime),
+    /// Migrated file, served directly from the source folder
+    File(PathBuf),
+}
+
+/// Destination of the build output
+#[derive(Default)]
+pub enum Output {
+    /// Write to the `site/` folder
+    #[default]
+    Disk,
+    /// Keep in memory (used by `wahoo serve`)
+    Memory(AHashMap<PathBuf, Entry>),
+}
+
+/// Callback notified of build stages (used by `wahoo serve`)
+pub type ProgressFn = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// Timing of the last completed build
+#[derive(Debug, Clone, Serialize)]
+pub struct BuildInfo {
+    /// completion time in milliseconds since the unix epoch
+    pub finished: u64,
+    pub duration: u64,
+    /// number of builds since startup
+    pub count: u64,
+}
+
 #[derive(Default)]
 pub struct Inner {
     ready: bool,
+    output: Output,
     migrate_files: AHashMap<PathBuf, MigrateFileInfo>,
     migrate_folders: AHashMap<PathBuf, MigrateFolderInfo>,
     scan: u64,
+    rendered: AHashMap<PathBuf, u64>,
+    render_scan: u64,
     // render: AHashMap<PathBuf, RenderCache>,
     updates: Updates,
+    dependencies: Dependencies,
+    errors: Vec<BuildError>,
+    warnings: Vec<String>,
+    started: Option<Instant>,
+    build: Option<BuildInfo>,
+    progress: Option<ProgressFn>,
 }
 
 // impl Inner {
@@ -70,15 +117,216 @@ impl Sink {
     //     }
     // }
 
+    /// Create a sink that keeps the build output in memory
+    pub fn memory() -> Self {
+        let inner = Inner {
+            output: Output::Memory(AHashMap::new()),
+            ..Inner::default()
+        };
+
+        Self {
+            inner: Arc::new(Mutex::new(inner)),
+        }
+    }
+
+    pub fn is_memory(&self) -> bool {
+        matches!(self.inner().output, Output::Memory(_))
+    }
+
+    /// Write rendered content to `file` relative to the site folder
+    pub fn write(&self, ctx: &Context, file: &Path, content: &[u8]) -> Result<()> {
+        let mut inner = self.inner();
+        let render_scan = inner.render_scan;
+        inner.rendered.insert(file.to_path_buf(), render_scan);
+        match &mut inner.output {
+            Output::Disk => {
+                let target_file = ctx.site_folder.join(file);
+                let folder = target_file.parent().unwrap();
+                if !folder.exists() {
+                    std::fs::create_dir_all(folder)?;
+                }
+                std::fs::write(target_file, content)?;
+            }
+            Output::Memory(entries) => {
+                // retain the timestamp of unchanged content
+                // so that browser caches remain valid
+                if let Some(Entry::Content(existing, _)) = entries.get(file) {
+                    if existing.as_slice() == content {
+                        return Ok(());
+                    }
+                }
+                entries.insert(
+                    file.to_path_buf(),
+                    Entry::Content(Arc::new(content.to_vec()), SystemTime::now()),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Check if `file` relative to the site folder has been produced
+    pub fn exists(&self, ctx: &Context, file: &Path) -> bool {
+        match &self.inner().output {
+            Output::Disk => ctx.site_folder.join(file).exists(),
+            Output::Memory(entries) => entries.contains_key(file),
+        }
+    }
+
+    /// Lookup `file` relative to the site folder in the in-memory store
+    pub fn get(&self, file: &Path) -> Option<Entry> {
+        match &self.inner().output {
+            Output::Disk => None,
+            Output::Memory(entries) => entries.get(file).cloned(),
+        }
+    }
+
     pub fn inner(&self) -> MutexGuard<'_, Inner> {
         self.inner.lock().unwrap()
     }
 
     pub fn begin(&self) {
-        self.inner().updates.clear();
+        let mut inner = self.inner();
+        inner.updates.clear();
+        inner.dependencies.clear();
+        inner.errors.clear();
+        inner.warnings.clear();
+        inner.started = Some(Instant::now());
+        inner.render_scan += 1;
     }
 
-    pub fn finish(&self) {}
+    /// Retain a previously rendered file that was not re-rendered
+    /// during the current build (i.e. due to a render error)
+    pub fn keep(&self, file: &Path) {
+        let mut inner = self.inner();
+        let render_scan = inner.render_scan;
+        if let Some(scan) = inner.rendered.get_mut(file) {
+            *scan = render_scan;
+        }
+    }
+
+    /// Retain all previously rendered files (used when rendering is aborted)
+    pub fn keep_all(&self) {
+        let mut inner = self.inner();
+        let render_scan = inner.render_scan;
+        inner
+            .rendered
+            .values_mut()
+            .for_each(|scan| *scan = render_scan);
+    }
+
+    /// Register an error encountered during the build
+    pub fn error(&self, error: BuildError) {
+        self.inner().errors.push(error);
+    }
+
+    /// Errors encountered during the last build
+    pub fn errors(&self) -> Vec<BuildError> {
+        self.inner().errors.clone()
+    }
+
+    pub fn on_progress(&self, progress: ProgressFn) {
+        self.inner().progress = Some(progress);
+    }
+
+    /// Report the current build stage
+    pub fn progress(&self, stage: &str) {
+        let progress = self.inner().progress.clone();
+        if let Some(progress) = progress {
+            progress(stage);
+        }
+    }
+
+    /// Register a warning emitted during the build
+    pub fn warning(&self, warning: String) {
+        self.inner().warnings.push(warning);
+    }
+
+    /// Warnings emitted during the last build
+    pub fn warnings(&self) -> Vec<String> {
+        self.inner().warnings.clone()
+    }
+
+    /// Timing of the last completed build
+    pub fn build_info(&self) -> Option<BuildInfo> {
+        self.inner().build.clone()
+    }
+
+    /// Rendered pages along with the files they were rendered from
+    pub fn pages(&self) -> Vec<(String, Vec<PathBuf>)> {
+        self.inner().dependencies.pages()
+    }
+
+    /// Register template references discovered during the build
+    pub fn scan_templates<'a, I>(&self, ctx: &Context, templates: I)
+    where
+        I: Iterator<Item = &'a str>,
+    {
+        self.inner().dependencies.scan(ctx, templates);
+    }
+
+    /// Register the files the page index has been collected from
+    pub fn collected(&self, sources: &[PathBuf]) {
+        self.inner().dependencies.collected(sources);
+    }
+
+    /// Register a rendered page along with the files it was rendered from
+    pub fn page(&self, url: &str, sources: &[PathBuf]) {
+        self.inner().dependencies.page(url, sources);
+    }
+
+    /// Returns urls of the pages affected by the changed files
+    pub fn affected(&self, changed: &[PathBuf]) -> Vec<String> {
+        self.inner().dependencies.affected(changed)
+    }
+
+    /// Remove rendered files that were not produced by the current build
+    /// (i.e. their source templates have been deleted or renamed)
+    pub fn finish(&self, ctx: &Context) {
+        let mut inner = self.inner();
+        if let Some(started) = inner.started.take() {
+            let finished = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_millis() as u64)
+                .unwrap_or_default();
+            let count = inner
+                .build
+                .as_ref()
+                .map(|build| build.count)
+                .unwrap_or_default()
+                + 1;
+            inner.build = Some(BuildInfo {
+                finished,
+                duration: started.elapsed().as_millis() as u64,
+                count,
+            });
+        }
+
+        let Inner {
+            output,
+            rendered,
+            render_scan,
+            ..
+        } = &mut *inner;
+
+        rendered.retain(|file, scan| {
+            if scan == render_scan {
+                return true;
+            }
+
+            log_info!("Remove", "file: {}", file.display());
+            match output {
+                Output::Disk => {
+                    std::fs::remove_file(ctx.site_folder.join(file)).unwrap_or_else(|err| {
+                        log_error!("Unable to remove file `{}`: {err}", file.display());
+                    });
+                }
+                Output::Memory(entries) => {
+                    entries.remove(file);
+                }
+            }
+            false
+        });
+    }
 
     pub fn init_state(&self) -> bool {
         let mut inner = self.inner();
@@ -91,7 +339,7 @@ impl Sink {
     }
 
     pub async fn init(&self, ctx: &Arc<Context>) -> Result<()> {
-        if self.init_state() {
+        if self.init_state() && !self.is_memory() {
             log_info!("Clean", "cleaning up target...");
             ctx.clean().await?;
             ctx.ensure_folders().await?;
@@ -99,7 +347,8 @@ impl Sink {
         Ok(())
     }
 
-    pub fn migrate(&self, ctx: &Context, list: &[PathBuf]) -> Result<()> {
+    /// Migrate `(file, source)` pairs, `file` being relative to the site folder
+    pub fn migrate(&self, ctx: &Context, list: &[(PathBuf, PathBuf)]) -> Result<()> {
         let mut inner = self.inner();
         let mut copy_files = Vec::new();
         let mut create_folders = AHashSet::new();
@@ -107,27 +356,29 @@ impl Sink {
         inner.scan += 1;
         let scan = inner.scan;
 
-        for file in list.iter() {
-            let from = ctx.src_folder.join(file);
-            let modified = std::fs::metadata(&from)?.modified()?;
+        for (file, from) in list.iter() {
+            let modified = std::fs::metadata(from)?.modified()?;
             match inner.migrate_files.get_mut(file) {
                 Some(entry) => {
                     entry.scan = scan;
-                    if entry.modified != modified {
+                    // a project file overriding a theme file replaces its source
+                    if entry.modified != modified || &entry.source != from {
+                        entry.source = from.clone();
                         entry.modified = modified;
-                        copy_files.push(file);
+                        copy_files.push((file, from));
                     }
                 }
                 None => {
-                    inner
-                        .migrate_files
-                        .insert(file.to_owned(), MigrateFileInfo::new(modified, scan));
-                    copy_files.push(file);
+                    inner.migrate_files.insert(
+                        file.to_owned(),
+                        MigrateFileInfo::new(from.clone(), modified, scan),
+                    );
+                    copy_files.push((file, from));
                 }
             };
 
             if let Some(folder) = file.parent() {
-                if folder.to_string_lossy().len() != 0 {
+                if !folder.to_string_lossy().is_empty() {
                     match inner.migrate_folders.get_mut(folder) {
                         Some(entry) => {
                             entry.scan = scan;
@@ -143,8 +394,35 @@ impl Sink {
             }
         }
 
+        let Inner {
+            output,
+            migrate_files,
+            migrate_folders,
+            ..
+        } = &mut *inner;
+
+        if let Output::Memory(entries) = output {
+            migrate_files.retain(|k, v| {
+                if v.scan != scan {
+                    log_info!("Remove", "file: {}", k.display());
+                    entries.remove(k);
+                    false
+                } else {
+                    true
+                }
+            });
+            migrate_folders.retain(|_, f| f.scan == scan);
+
+            for (file, from) in copy_files.iter() {
+                log_trace!("Migrate", "{} `{}`", style("file:").cyan(), file.display());
+                entries.insert(file.to_path_buf(), Entry::File(from.to_path_buf()));
+            }
+
+            return Ok(());
+        }
+
         // remove missing files
-        inner.migrate_files.retain(|k, v| {
+        migrate_files.retain(|k, v| {
             if v.scan != scan {
                 log_info!("Remove", "file: {}", k.display());
                 std::fs::remove_file(ctx.site_folder.join(k)).unwrap_or_else(|err| {
@@ -157,8 +435,8 @@ impl Sink {
         });
 
         // remove missing folders
-        for (folder, f) in inner.migrate_folders.iter() {
-            if f.scan != inner.scan {
+        for (folder, f) in migrate_folders.iter() {
+            if f.scan != scan {
                 log_info!("Remove", "folder: {}", folder.display());
                 std::fs::remove_dir_all(ctx.site_folder.join(folder)).ok();
             }
@@ -176,7 +454,7 @@ impl Sink {
         }
 
         // copy new files
-        for file in copy_files.iter() {
+        for (file, from) in copy_files.iter() {
             let to_file = ctx.site_folder.join(file);
             log_trace!(
                 "Migrate",
@@ -185,7 +463,7 @@ impl Sink {
                 file.display(),
                 to_file.display()
             );
-            std::fs::copy(ctx.src_folder.join(file), to_file)?;
+            std::fs::copy(from, to_file)?;
         }
 
         Ok(())
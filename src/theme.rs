@@ -0,0 +1,75 @@
+use crate::prelude::*;
+use crate::schema::FileKind;
+
+/// Theme file holding the default template data of a theme
+pub const THEME_FILE: &str = "theme.toml";
+/// Templates folder of a theme, overridden by the project templates
+pub const THEME_TEMPLATES: &str = "templates";
+/// Static assets folder of a theme, overridden by the project source folder
+pub const THEME_STATIC: &str = "static";
+
+/// A theme folder selected by `settings.theme`
+#[derive(Debug, Clone)]
+pub struct Theme {
+    pub folder: PathBuf,
+    /// default template data, overridden by the manifest
+    pub toml: toml::Value,
+}
+
+impl Theme {
+    pub fn templates_folder(&self) -> PathBuf {
+        self.folder.join(THEME_TEMPLATES)
+    }
+
+    pub fn static_folder(&self) -> PathBuf {
+        self.folder.join(THEME_STATIC)
+    }
+
+    /// Load the theme located at `location` (relative to `folder`) followed by
+    /// the themes it extends, each theme's `extends` being relative to its folder
+    pub async fn load_chain(
+        folder: &Path,
+        location: &str,
+        warnings: &mut Vec<String>,
+    ) -> Result<Vec<Theme>> {
+        let mut themes: Vec<Theme> = vec![];
+        let mut next = Some(folder.join(location));
+        while let Some(location) = next.take() {
+            let folder = location.canonicalize().map_err(|err| -> Error {
+                format!("Unable to locate theme `{}`: {err}", location.display()).into()
+            })?;
+            if themes.iter().any(|theme| theme.folder == folder) {
+                let chain = themes
+                    .iter()
+                    .map(|theme| &theme.folder)
+                    .chain([&folder])
+                    .map(|folder| format!("`{}`", folder.display()))
+                    .collect::<Vec<_>>()
+                    .join(" -> ");
+                return Err(format!("Theme cycle detected: {chain}").into());
+            }
+
+            let mut toml = if folder.join(THEME_FILE).is_file() {
+                Manifest::load_toml(&folder, THEME_FILE, FileKind::Theme, warnings).await?
+            } else {
+                toml::Value::Table(toml::Table::new())
+            };
+            if let Some(extends) = toml
+                .as_table_mut()
+                .and_then(|table| table.remove("extends"))
+            {
+                let extends = extends.as_str().ok_or_else(|| {
+                    format!(
+                        "Error loading `{}`: `extends` must be a string",
+                        folder.join(THEME_FILE).display()
+                    )
+                })?;
+                next = Some(folder.join(extends));
+            }
+
+            themes.push(Theme { folder, toml });
+        }
+
+        Ok(themes)
+    }
+}
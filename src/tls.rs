@@ -0,0 +1,278 @@
+use crate::prelude::*;
+use async_std::net::{SocketAddr, TcpListener, TcpStream};
+use async_std::{io, task};
+use futures::StreamExt;
+use futures_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+use futures_rustls::TlsAcceptor;
+use std::fmt;
+use tide::listener::{ListenInfo, Listener, ToListener};
+
+/// Host names the self-signed certificate is always valid for
+const LOCAL_NAMES: &[&str] = &["localhost", "127.0.0.1", "::1"];
+
+/// TLS configuration used by `wahoo serve --https`
+#[derive(Clone)]
+pub struct Tls {
+    config: Arc<ServerConfig>,
+}
+
+impl Tls {
+    /// Load a PEM encoded certificate chain and private key
+    pub fn load(cert: &Path, key: &Path) -> Result<Tls> {
+        let certs = {
+            let mut reader = std::io::BufReader::new(std::fs::File::open(cert)?);
+            rustls_pemfile::certs(&mut reader)?
+        };
+        if certs.is_empty() {
+            return Err(format!("No certificates found in `{}`", cert.display()).into());
+        }
+
+        let mut reader = std::io::BufReader::new(std::fs::File::open(key)?);
+        let key = loop {
+            match rustls_pemfile::read_one(&mut reader)? {
+                Some(rustls_pemfile::Item::PKCS8Key(key))
+                | Some(rustls_pemfile::Item::RSAKey(key))
+                | Some(rustls_pemfile::Item::ECKey(key)) => break key,
+                Some(_) => continue,
+                None => return Err(format!("No private key found in `{}`", key.display()).into()),
+            }
+        };
+
+        let config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                certs.into_iter().map(Certificate).collect(),
+                PrivateKey(key),
+            )
+            .map_err(|err| format!("Invalid TLS certificate `{}`: {err}", cert.display()))?;
+
+        Ok(Tls {
+            config: Arc::new(config),
+        })
+    }
+
+    /// Load a self-signed certificate for `hosts`, generating it on first
+    /// use and caching it in `~/.wahoo/certs` so that a browser exception
+    /// only has to be granted once; the certificate is regenerated when the
+    /// names it is valid for change (i.e. a new LAN address)
+    pub fn self_signed(hosts: &[String]) -> Result<Tls> {
+        let mut names = LOCAL_NAMES
+            .iter()
+            .map(|name| name.to_string())
+            .collect::<Vec<_>>();
+        let mut extra = Vec::new();
+        for host in hosts {
+            let host = host.trim_start_matches('[').trim_end_matches(']');
+            let unspecified = host == "0.0.0.0" || host == "::";
+            if unspecified {
+                // the server is reachable on the LAN addresses of the machine
+                for address in lan_addresses(host == "::") {
+                    if !names.contains(&address) {
+                        names.push(address);
+                    }
+                }
+                extra.push("lan".to_string());
+            } else if !names.iter().any(|name| name == host) {
+                names.push(host.to_string());
+                extra.push(host.replace(':', "_"));
+            }
+        }
+        extra.dedup();
+
+        let folder = home::home_dir()
+            .ok_or("unable to get home directory")?
+            .join(".wahoo")
+            .join("certs");
+        let name = if extra.is_empty() {
+            "localhost".to_string()
+        } else {
+            extra.join("+")
+        };
+        let cert = folder.join(format!("{name}.pem"));
+        let key = folder.join(format!("{name}.key"));
+        // names the cached certificate was generated for
+        let names_file = folder.join(format!("{name}.names"));
+        let names_list = names.join("\n");
+        let outdated = std::fs::read_to_string(&names_file)
+            .map(|cached| cached != names_list)
+            .unwrap_or(true);
+
+        if !cert.is_file() || !key.is_file() || outdated {
+            log_info!(
+                "HTTPS",
+                "generating self-signed certificate for {}",
+                names.join(", ")
+            );
+            let certificate = rcgen::generate_simple_self_signed(names)
+                .map_err(|err| format!("Unable to generate certificate: {err}"))?;
+            let pem = certificate
+                .serialize_pem()
+                .map_err(|err| format!("Unable to generate certificate: {err}"))?;
+            std::fs::create_dir_all(&folder)?;
+            std::fs::write(&cert, pem)?;
+            write_private(&key, certificate.serialize_private_key_pem().as_bytes())?;
+            std::fs::write(&names_file, names_list)?;
+        }
+        log_info!("HTTPS", "using certificate `{}`", cert.display());
+
+        Tls::load(&cert, &key)
+    }
+
+    /// Create a tide listener accepting TLS connections on a bound `listener`
+    pub fn listener<State>(&self, listener: TcpListener) -> io::Result<TlsListener<State>> {
+        Ok(TlsListener {
+            address: listener.local_addr()?,
+            acceptor: TlsAcceptor::from(self.config.clone()),
+            listener: Some(listener),
+            server: None,
+            info: None,
+        })
+    }
+}
+
+/// Tide listener serving the app over TLS, including websocket upgrades
+pub struct TlsListener<State> {
+    address: SocketAddr,
+    acceptor: TlsAcceptor,
+    listener: Option<TcpListener>,
+    server: Option<tide::Server<State>>,
+    info: Option<ListenInfo>,
+}
+
+impl<State> fmt::Debug for TlsListener<State> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsListener")
+            .field("address", &self.address)
+            .field("listener", &self.listener)
+            .finish()
+    }
+}
+
+impl<State> fmt::Display for TlsListener<State> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "https://{}", self.address)
+    }
+}
+
+#[tide::utils::async_trait]
+impl<State> Listener<State> for TlsListener<State>
+where
+    State: Clone + Send + Sync + 'static,
+{
+    async fn bind(&mut self, server: tide::Server<State>) -> io::Result<()> {
+        self.server = Some(server);
+        self.info = Some(ListenInfo::new(self.to_string(), "tcp".to_string(), true));
+        Ok(())
+    }
+
+    async fn accept(&mut self) -> io::Result<()> {
+        let server = self
+            .server
+            .take()
+            .expect("`Listener::bind` must be called before `Listener::accept`");
+        let listener = self
+            .listener
+            .take()
+            .expect("`Listener::bind` must be called before `Listener::accept`");
+
+        let mut incoming = listener.incoming();
+        while let Some(stream) = incoming.next().await {
+            match stream {
+                Ok(stream) => handle_tls(server.clone(), self.acceptor.clone(), stream),
+                Err(err) => log_trace!("HTTPS", "unable to accept connection: {err}"),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn info(&self) -> Vec<ListenInfo> {
+        self.info.iter().cloned().collect()
+    }
+}
+
+impl<State> ToListener<State> for TlsListener<State>
+where
+    State: Clone + Send + Sync + 'static,
+{
+    type Listener = Self;
+
+    fn to_listener(self) -> io::Result<Self::Listener> {
+        Ok(self)
+    }
+}
+
+/// Local addresses used to reach other machines, found by connecting
+/// (without sending anything) an UDP socket to a public address
+fn lan_addresses(ipv6: bool) -> Vec<String> {
+    let mut targets = vec![("0.0.0.0:0", "192.0.2.1:80")];
+    if ipv6 {
+        targets.push(("[::]:0", "[2001:db8::1]:80"));
+    }
+    targets
+        .into_iter()
+        .filter_map(|(local, remote)| {
+            let socket = std::net::UdpSocket::bind(local).ok()?;
+            socket.connect(remote).ok()?;
+            let address = socket.local_addr().ok()?.ip();
+            (!address.is_unspecified() && !address.is_loopback()).then(|| address.to_string())
+        })
+        .collect()
+}
+
+/// Write a private key readable by the current user only
+fn write_private(path: &Path, content: &[u8]) -> Result<()> {
+    use std::io::Write;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    // the mode only applies to new files
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    }
+    file.write_all(content)?;
+    Ok(())
+}
+
+fn handle_tls<State>(app: tide::Server<State>, acceptor: TlsAcceptor, stream: TcpStream)
+where
+    State: Clone + Send + Sync + 'static,
+{
+    task::spawn(async move {
+        let local_addr = stream.local_addr().ok();
+        let peer_addr = stream.peer_addr().ok();
+
+        let stream = match acceptor.accept(stream).await {
+            Ok(stream) => stream,
+            Err(err) => {
+                // i.e. the browser rejecting the self-signed certificate
+                log_trace!("HTTPS", "TLS handshake failed: {err}");
+                return;
+            }
+        };
+
+        // async-h1 requires a cloneable stream; the same stream
+        // is handed over to the websocket on connection upgrade
+        let stream = async_dup::Arc::new(async_dup::Mutex::new(stream));
+        let result = async_h1::accept(stream, |mut request| async {
+            request.url_mut().set_scheme("https").ok();
+            request.set_local_addr(local_addr);
+            request.set_peer_addr(peer_addr);
+            app.respond(request).await
+        })
+        .await;
+
+        if let Err(err) = result {
+            log_trace!("HTTPS", "connection error: {err}");
+        }
+    });
+}
@@ -52,10 +52,9 @@ pub fn get_env_defs(strings: &Vec<String>) -> Result<Vec<(String, String)>> {
     let mut parsed_strings = Vec::new();
 
     for string in strings {
-        let captures = regex.captures(string).unwrap();
-        if captures.len() != 2 {
-            return Err(format!("Error parsing the environment string: '{string}'").into());
-        }
+        let Some(captures) = regex.captures(string) else {
+            return Err(format!("Error parsing '{string}', expected `KEY=VALUE`").into());
+        };
         let a = captures[1].to_string();
         let b = captures[2].to_string();
 
@@ -98,3 +97,48 @@ where
 
     None
 }
+
+/// Resolve `.` and `..` path components without touching the filesystem
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                result.pop();
+            }
+            _ => result.push(component),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_definitions() {
+        let defs = get_env_defs(&vec![
+            "site.name=a".to_string(),
+            "site.title=a=b".to_string(),
+            " key = value ".to_string(),
+        ])
+        .unwrap();
+        assert_eq!(
+            defs,
+            vec![
+                ("site.name".to_string(), "a".to_string()),
+                ("site.title".to_string(), "a=b".to_string()),
+                (" key ".to_string(), " value ".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_definitions_without_value() {
+        assert!(get_env_defs(&vec!["site.name".to_string()]).is_err());
+        assert!(get_env_defs(&vec!["site.name=".to_string()]).is_err());
+        assert!(get_env_defs(&vec!["=value".to_string()]).is_err());
+    }
+}
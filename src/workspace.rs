@@ -0,0 +1,192 @@
+use crate::prelude::*;
+use schemars::JsonSchema;
+
+/// `[workspace]` manifest table listing the member projects of a multi-site workspace
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct WorkspaceSettings {
+    /// member project folders, relative to the workspace folder
+    pub members: Vec<MemberSettings>,
+    /// combined output folder, relative to the workspace folder; members
+    /// are built into `<output>/<prefix>` instead of their own output folder
+    pub output: Option<String>,
+    /// template folders shared by all members, relative to the workspace folder
+    pub templates: Option<Vec<String>>,
+    /// TOML files imported by all members, relative to the workspace folder
+    pub import: Option<Vec<String>>,
+}
+
+/// `workspace.members` entry: a folder or a table with a path prefix
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum MemberSettings {
+    Path(String),
+    Member(Member),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct Member {
+    pub path: String,
+    /// path prefix the member is served from (default: the folder name)
+    pub prefix: Option<String>,
+}
+
+impl MemberSettings {
+    pub fn path(&self) -> &str {
+        match self {
+            MemberSettings::Path(path) => path,
+            MemberSettings::Member(member) => &member.path,
+        }
+    }
+
+    /// Path prefix without leading and trailing slashes (empty for the root)
+    pub fn prefix(&self) -> String {
+        let prefix = match self {
+            MemberSettings::Member(Member {
+                prefix: Some(prefix),
+                ..
+            }) => prefix.clone(),
+            _ => Path::new(self.path())
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default(),
+        };
+        prefix.trim_matches('/').to_string()
+    }
+}
+
+/// A site built by `wahoo build` and `wahoo serve`
+pub struct Site {
+    /// path prefix without leading and trailing slashes (empty for the root)
+    pub prefix: String,
+    pub ctx: Arc<Context>,
+}
+
+/// The located project: a single site, or the member sites of a workspace
+pub struct Project {
+    pub manifest_toml: PathBuf,
+    pub folder: PathBuf,
+    pub serve: Option<ServeSettings>,
+    pub workspace: Option<WorkspaceSettings>,
+    pub sites: Vec<Site>,
+}
+
+impl Project {
+    pub async fn load(location: Option<String>, options: &Options) -> Result<Project> {
+        let manifest_toml = Manifest::locate(location).await?;
+        let manifest = Manifest::load(&manifest_toml, options).await?;
+        let folder = manifest_toml.parent().unwrap().to_path_buf();
+
+        let Some(workspace) = manifest.workspace.clone() else {
+            let ctx =
+                Context::with_manifest(manifest_toml.clone(), manifest, options.clone()).await?;
+            let ctx = Arc::new(ctx);
+            return Ok(Project {
+                manifest_toml,
+                folder,
+                serve: ctx.manifest.serve.clone(),
+                workspace: None,
+                sites: vec![Site {
+                    prefix: String::new(),
+                    ctx,
+                }],
+            });
+        };
+
+        if workspace.members.is_empty() {
+            return Err("`workspace.members` is empty".into());
+        }
+
+        let paths = |list: &Option<Vec<String>>| -> Result<Vec<PathBuf>> {
+            let mut paths = vec![];
+            for path in list.iter().flatten() {
+                let path = folder.join(path);
+                paths.push(path.canonicalize().map_err(|err| -> Error {
+                    format!("Unable to locate `{}`: {err}", path.display()).into()
+                })?);
+            }
+            Ok(paths)
+        };
+        let templates = paths(&workspace.templates)?;
+        let imports = paths(&workspace.import)?;
+
+        // the combined output tree of `--output` or `workspace.output`
+        let output = match (&options.output, &workspace.output) {
+            (Some(output), _) => Some(current_dir().await.join(output)),
+            (None, Some(output)) => Some(folder.join(output)),
+            (None, None) => None,
+        };
+
+        let mut sites = vec![];
+        for member in workspace.members.iter() {
+            let prefix = member.prefix();
+            if sites.iter().any(|site: &Site| site.prefix == prefix) {
+                return Err(format!("Duplicate workspace member prefix `/{prefix}`").into());
+            }
+
+            // members are mounted under their prefix by the dev server
+            // and when built into a combined output tree
+            let base_path = if options.server || output.is_some() {
+                format!("/{prefix}/").replace("//", "/")
+            } else {
+                "/".to_string()
+            };
+            let options = Options {
+                output: output.as_ref().map(|output| output.join(&prefix)),
+                member: Some(MemberOptions {
+                    base_path,
+                    templates: templates.clone(),
+                    imports: imports.clone(),
+                }),
+                ..options.clone()
+            };
+
+            let location = folder.join(member.path()).to_string_lossy().to_string();
+            let ctx = Context::create(Some(location), options)
+                .await
+                .map_err(|err| format!("Workspace member `{}`: {err}", member.path()))?;
+            sites.push(Site {
+                prefix,
+                ctx: Arc::new(ctx),
+            });
+        }
+
+        // a member built into the root of the combined output tree
+        // cleans it, so it is built before the members nested in it
+        sites.sort_by_key(|site| site.prefix.len());
+
+        Ok(Project {
+            manifest_toml,
+            folder,
+            serve: manifest.serve,
+            workspace: Some(workspace),
+            sites,
+        })
+    }
+
+    /// Sites whose inputs include the changed `file`; shared templates
+    /// and imports are inputs of each member
+    pub fn sites_of(&self, file: &Path) -> Vec<&Site> {
+        self.sites
+            .iter()
+            .filter(|site| {
+                site.ctx
+                    .watch_targets()
+                    .iter()
+                    .any(|target| file.starts_with(target))
+            })
+            .collect()
+    }
+
+    /// Files and folders monitored for changes in `serve` mode
+    pub fn watch_targets(&self) -> Vec<PathBuf> {
+        let mut watch_targets = vec![self.manifest_toml.clone()];
+        for site in self.sites.iter() {
+            watch_targets.extend(site.ctx.watch_targets());
+        }
+        watch_targets.sort();
+        watch_targets.dedup();
+        watch_targets
+    }
+}